@@ -1,3 +1,5 @@
+#pragma once
+
 #define LAMBERTIAN 0
 #define METAL 1
 #define DIELECTRIC 2
@@ -1,8 +1,8 @@
-/* <ignore> */
+#pragma once
+
 #include "../utils/math.glsl"
 #include "../utils/ray_utils.glsl"
 #include "hit_record.glsl"
-/* </ignore> */
 
 bool scatter_lambertian(in Ray ray, in HitRecord hit_record, in Material material, out vec3 attenuation, out Ray scattered) {
     vec3 scatter_direction = normalize(hit_record.normal + random_timed_vec3(ray.pixel));
@@ -1,7 +1,7 @@
-/* <ignore> */
+#pragma once
+
 #include "material.glsl"
 #include "../utils/sdf_funcs.glsl"
-/* </ignore> */
 
 struct Sphere {
     vec3 center;
@@ -1,3 +1,5 @@
+#pragma once
+
 #define PI 3.14159265
 
 // Random hash generation code is completely from https://stackoverflow.com/a/17479300
@@ -1,3 +1,5 @@
+#pragma once
+
 struct Ray {
     vec2 pixel;
     vec3 origin;
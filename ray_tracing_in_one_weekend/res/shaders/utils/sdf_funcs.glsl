@@ -1,3 +1,5 @@
+#pragma once
+
 float sdf_sphere(in vec3 origin, in float radius, in vec3 ray_origin, in vec3 ray_direction) {
     vec3 oc = origin - ray_origin;
     float a = dot(ray_direction, ray_direction);
@@ -1,34 +1,77 @@
+use render::Command;
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 use sdl2::EventPump;
 use shader_engine::image_buffer::Image2D;
+use shader_engine::image_export::ExportOptions;
 use shader_engine::raw_model::RawModel;
+use shader_engine::embed_shaders;
+use shader_engine::shader::barrier::MemoryBarrier;
+use shader_engine::shader::diagnostic::ShaderError;
+use shader_engine::shader::options::ShaderOptions;
 use shader_engine::shader::{ComputeShader, GraphicsShader, ShaderProgram};
 use shader_engine::utils::html_logger::HTMLLogger;
 use shader_engine::{MainLoopResult, ShaderEngine};
-use std::error;
+use std::{env, error, process};
+use world_gen::Sphere;
+
+mod render;
+mod world_gen;
 
 const START_WIDTH: i32 = 800;
 const START_HEIGHT: i32 = 800;
 
+// Shaders are found relative to the crate, so the project can be started from any directory
+const SHADER_ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/res/shaders");
+
+// Saved with `P`, relative to the working directory
+const RENDER_PATH: &str = "ray_tracing_render.png";
+
+const SAMPLE_PER_PIXEL: u32 = 4;
+// Upper bound of the `Up` key, more samples per dispatch stall the GPU for too long
+const MAX_SAMPLE_PER_PIXEL: u32 = 1024;
+const MAX_SPHERES: u32 = 16;
+const MAX_BOUNCES: u32 = 5;
+const SCENE_SEED: [f32; 2] = [12.51, 8.63];
+
 fn main() -> Result<(), Box<dyn error::Error>> {
+    let command = Command::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    match command {
+        Command::Interactive => run_window(),
+        Command::Render(settings) => render::render(settings),
+        Command::Help => {
+            println!("{}", Command::usage());
+            Ok(())
+        }
+    }
+}
+
+/// Opens the scene in a window, rendering it again every frame
+fn run_window() -> Result<(), Box<dyn error::Error>> {
     let mut shader_engine = ShaderEngine::create_window("Ray Tracing", START_WIDTH, START_HEIGHT)?;
 
     // Set the window features
-    {
-        shader_engine.window_builder.resizable();
+    if let Some(window_builder) = shader_engine.window_builder.as_mut() {
+        window_builder.resizable();
     }
     shader_engine.finalize_window()?;
+    shader_engine.set_screenshot_key(Some(Keycode::F12))?;
 
     let mut html_logger = HTMLLogger::new("Ray Tracing");
 
-    let mut ray_tracing_compute =
-        ShaderProgram::<ComputeShader>::new(&mut html_logger, "RT Shader", "main.comp")?;
+    let mut ray_tracing_compute = ray_tracing_program(&mut html_logger, SAMPLE_PER_PIXEL, false)?;
 
     let resolution_uniform = ray_tracing_compute
         .get_uniform::<[f32; 2]>("resolution")
         .unwrap();
     let time_uniform = ray_tracing_compute.get_uniform::<f32>("time").unwrap();
+    let spheres_uniform = ray_tracing_compute
+        .get_uniform::<Vec<Sphere>>("spheres")
+        .unwrap();
 
     // Set resolution uniform
     {
@@ -38,6 +81,14 @@ fn main() -> Result<(), Box<dyn error::Error>> {
             .set([START_WIDTH as f32, START_HEIGHT as f32]);
     }
 
+    // Set the scene
+    {
+        spheres_uniform
+            .borrow_mut()
+            .get_bind()
+            .set(world_gen::setup_scene(SCENE_SEED, MAX_SPHERES));
+    }
+
     // Necessary images for fragment shader, required for noise reduction
     let mut screen_image = Image2D::new(
         START_WIDTH,
@@ -62,8 +113,10 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         "Quad Shader",
         "quad.vert",
         "quad.frag",
+        shader_options(),
     )?;
 
+    let mut sample_per_pixel = SAMPLE_PER_PIXEL;
     shader_engine.set_loop(
         move |event_pump: &mut EventPump, elapsed_time: f64| -> MainLoopResult {
             // Check shaders for updates, any change will update the shaders
@@ -84,6 +137,31 @@ fn main() -> Result<(), Box<dyn error::Error>> {
                         keycode: Some(Keycode::Escape),
                         ..
                     } => return MainLoopResult::Quit,
+                    Event::KeyDown {
+                        keycode: Some(keycode @ (Keycode::Up | Keycode::Down)),
+                        ..
+                    } => {
+                        // Change the sample count, this recompiles the ray tracing shader
+                        sample_per_pixel = match keycode {
+                            Keycode::Up => (sample_per_pixel * 2).min(MAX_SAMPLE_PER_PIXEL),
+                            _ => (sample_per_pixel / 2).max(1),
+                        };
+                        ray_tracing_compute.set_define(
+                            &mut html_logger,
+                            "SAMPLE_PER_PIXEL",
+                            sample_per_pixel,
+                        );
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::P),
+                        ..
+                    } => {
+                        // The shader already applies the gamma, so the image is saved as it is
+                        match screen_image.save_png(RENDER_PATH, &ExportOptions::new()) {
+                            Ok(()) => println!("Saved the render to {}", RENDER_PATH),
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    }
                     Event::Window {
                         win_event: WindowEvent::Resized(width, height),
                         ..
@@ -114,9 +192,9 @@ fn main() -> Result<(), Box<dyn error::Error>> {
 
                 let mut borrow = resolution_uniform.borrow_mut();
                 let resolution = borrow.get_bind().get();
-                ray_tracing_compute.dispatch_compute(
-                    (resolution[0] / 8.0).ceil() as u32,
-                    (resolution[1] / 4.0).ceil() as u32,
+                ray_tracing_compute.dispatch_for_size(
+                    resolution[0] as u32,
+                    resolution[1] as u32,
                     1,
                 );
 
@@ -141,3 +219,48 @@ fn main() -> Result<(), Box<dyn error::Error>> {
 
     Ok(())
 }
+
+/// Builds the ray tracing compute shader, the window and the offline render share it
+/// `average_passes` makes every pass count the same and keeps the colors linear, for offline renders.
+/// Otherwise the recent passes weigh more and the colors are gamma encoded, ready for the window
+fn ray_tracing_program(
+    html_logger: &mut HTMLLogger,
+    sample_per_pixel: u32,
+    average_passes: bool,
+) -> Result<ShaderProgram<ComputeShader>, ShaderError> {
+    let mut ray_tracing_options = shader_options()
+        .define("SAMPLE_PER_PIXEL", sample_per_pixel)
+        .define("MAX_SPHERES", MAX_SPHERES)
+        .define("MAX_BOUNCES", MAX_BOUNCES)
+        .uniform_struct::<Sphere>()
+        // The next frame loads the images again and the quad shader samples the screen
+        .barrier(MemoryBarrier::IMAGE_ACCESS | MemoryBarrier::TEXTURE_FETCH);
+    if average_passes {
+        ray_tracing_options = ray_tracing_options.define("AVERAGE_PASSES", 1);
+    }
+    ShaderProgram::<ComputeShader>::new(html_logger, "RT Shader", "main.comp", ray_tracing_options)
+}
+
+/// Debug builds read the shaders from disk so they can be hot reloaded,
+/// release builds ship with the shaders compiled into the binary
+fn shader_options() -> ShaderOptions {
+    let options = ShaderOptions::new().line_directives(true);
+    if cfg!(debug_assertions) {
+        options.root(SHADER_ROOT)
+    } else {
+        options.source(embed_shaders!(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/res/shaders"),
+            [
+                "main.comp",
+                "quad.vert",
+                "quad.frag",
+                "ray_tracing/hit_record.glsl",
+                "ray_tracing/material.glsl",
+                "ray_tracing/sphere.glsl",
+                "utils/math.glsl",
+                "utils/ray_utils.glsl",
+                "utils/sdf_funcs.glsl",
+            ]
+        ))
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{ray_tracing_program, world_gen, MAX_SPHERES, SAMPLE_PER_PIXEL, SCENE_SEED};
+use shader_engine::image_buffer::Image2D;
+use shader_engine::image_export::ExportOptions;
+use shader_engine::utils::html_logger::HTMLLogger;
+use shader_engine::{MainLoopResult, ShaderEngine};
+use std::path::PathBuf;
+use std::{error, fs};
+
+/// Time step between two passes, the shader uses the time to pick different samples each pass
+const PASS_TIME: f32 = 1.0 / 60.0;
+
+const USAGE: &str = "\
+Usage: ray_tracing_in_one_weekend [render [OPTIONS]]
+
+Without arguments the scene opens in a window. `render` renders it without a window and saves the image.
+
+Options:
+    --width <PIXELS>     Width of the image [default: 800]
+    --height <PIXELS>    Height of the image [default: 800]
+    --spp <SAMPLES>      Samples per pixel to render, rounded up to passes of 4 [default: 256]
+    --seed <X,Y>         Seed of the generated scene [default: 12.51,8.63]
+    --output <PATH>      Where the image is saved, as .png, .hdr or .exr [default: render.png]
+    -h, --help           Prints this message";
+
+/// Settings of an offline render, read from the command line
+pub struct RenderSettings {
+    pub width: i32,
+    pub height: i32,
+    pub sample_count: u32,
+    pub seed: [f32; 2],
+    pub output: PathBuf,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 800,
+            height: 800,
+            sample_count: 256,
+            seed: SCENE_SEED,
+            output: PathBuf::from("render.png"),
+        }
+    }
+}
+
+/// What the command line asks for
+pub enum Command {
+    Interactive,
+    Render(RenderSettings),
+    Help,
+}
+
+impl Command {
+    /// Parses the arguments, without the program name
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+        match args.next().as_deref() {
+            None => return Ok(Command::Interactive),
+            Some("render") => {}
+            Some("-h" | "--help") => return Ok(Command::Help),
+            Some(other) => return Err(format!("Unknown command `{}`\n\n{}", other, USAGE)),
+        }
+
+        let mut settings = RenderSettings::default();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Ok(Command::Help);
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("`{}` needs a value\n\n{}", arg, USAGE))?;
+            match arg.as_str() {
+                "--width" => settings.width = parse_size(&arg, &value)?,
+                "--height" => settings.height = parse_size(&arg, &value)?,
+                "--spp" => {
+                    settings.sample_count = value
+                        .parse()
+                        .ok()
+                        .filter(|samples| *samples > 0)
+                        .ok_or_else(|| {
+                            format!("`--spp` takes a positive number, got `{}`", value)
+                        })?
+                }
+                "--seed" => settings.seed = parse_seed(&value)?,
+                "--output" => settings.output = PathBuf::from(value),
+                _ => return Err(format!("Unknown option `{}`\n\n{}", arg, USAGE)),
+            }
+        }
+
+        Ok(Command::Render(settings))
+    }
+
+    pub fn usage() -> &'static str {
+        USAGE
+    }
+}
+
+fn parse_size(arg: &str, value: &str) -> Result<i32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|size| *size > 0)
+        .ok_or_else(|| format!("`{}` takes a positive number, got `{}`", arg, value))
+}
+
+fn parse_seed(value: &str) -> Result<[f32; 2], String> {
+    let seed = value
+        .split_once(',')
+        .and_then(|(x, y)| Some([x.trim().parse().ok()?, y.trim().parse().ok()?]));
+    seed.ok_or_else(|| {
+        format!(
+            "`--seed` takes two numbers like `12.51,8.63`, got `{}`",
+            value
+        )
+    })
+}
+
+/// Accumulates the samples without a window, then saves the ray traced image
+pub fn render(settings: RenderSettings) -> Result<(), Box<dyn error::Error>> {
+    let RenderSettings {
+        width,
+        height,
+        sample_count,
+        seed,
+        output,
+    } = settings;
+
+    // Check the format before spending the time on the render
+    let extension = output
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    if !matches!(extension.as_deref(), Some("png" | "hdr" | "exr")) {
+        return Err(format!("Can't save {}, use .png, .hdr or .exr", output.display()).into());
+    }
+    if let Some(directory) = output
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(directory)?;
+    }
+
+    let mut shader_engine = ShaderEngine::create_headless(width, height)?;
+    let mut html_logger = HTMLLogger::new("Ray Tracing Render");
+
+    let mut ray_tracing_compute = ray_tracing_program(&mut html_logger, SAMPLE_PER_PIXEL, true)?;
+    ray_tracing_compute
+        .get_uniform::<[f32; 2]>("resolution")
+        .unwrap()
+        .borrow_mut()
+        .get_bind()
+        .set([width as f32, height as f32]);
+    ray_tracing_compute
+        .get_uniform::<Vec<world_gen::Sphere>>("spheres")
+        .unwrap()
+        .borrow_mut()
+        .get_bind()
+        .set(world_gen::setup_scene(seed, MAX_SPHERES));
+    let time_uniform = ray_tracing_compute.get_uniform::<f32>("time").unwrap();
+
+    let screen_image = Image2D::new(width, height, gl::READ_WRITE, gl::RGBA32F, gl::RGBA32F);
+    let frame_count_image = Image2D::new(width, height, gl::READ_WRITE, gl::R32F, gl::R32F);
+    // The first pass averages with what is already there, which has to be zero
+    screen_image.clear();
+    frame_count_image.clear();
+
+    // Each pass adds SAMPLE_PER_PIXEL samples to every pixel
+    let pass_count = sample_count.div_ceil(SAMPLE_PER_PIXEL);
+    println!(
+        "Rendering {}x{} with {} samples per pixel",
+        width,
+        height,
+        pass_count * SAMPLE_PER_PIXEL
+    );
+
+    shader_engine.set_frame_limit(Some(pass_count as u64));
+    let mut pass: u32 = 0;
+    shader_engine.set_headless_loop(|_| {
+        // The time only advances by passes, so the same settings give the same image
+        // while every pass still picks different samples
+        time_uniform
+            .borrow_mut()
+            .get_bind()
+            .set(pass as f32 * PASS_TIME);
+
+        ray_tracing_compute.toggle_use();
+        screen_image.bind_as_image(0);
+        frame_count_image.bind_as_image(1);
+        ray_tracing_compute.dispatch_for_size(width as u32, height as u32, 1);
+        ray_tracing_compute.toggle_use();
+
+        pass += 1;
+        if pass.is_multiple_of(16) || pass == pass_count {
+            println!("Pass {}/{}", pass, pass_count);
+        }
+        MainLoopResult::Continue
+    });
+
+    // The averaged colors are linear, only the PNG needs them gamma encoded
+    match extension.as_deref() {
+        Some("png") => screen_image.save_png(&output, &ExportOptions::new().gamma(2.2))?,
+        Some("hdr") => screen_image.save_hdr(&output, &ExportOptions::new())?,
+        _ => screen_image.save_exr(&output, &ExportOptions::new())?,
+    }
+    println!("Saved the render to {}", output.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn settings(args: &[&str]) -> RenderSettings {
+        match parse(args) {
+            Ok(Command::Render(settings)) => settings,
+            _ => panic!("{:?} is not a render", args),
+        }
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(parse(&[]), Ok(Command::Interactive)));
+        assert!(matches!(parse(&["--help"]), Ok(Command::Help)));
+        assert!(matches!(
+            parse(&["render", "--spp", "8", "-h"]),
+            Ok(Command::Help)
+        ));
+        assert!(parse(&["draw"]).is_err());
+    }
+
+    #[test]
+    fn render_defaults() {
+        let settings = settings(&["render"]);
+        assert_eq!((settings.width, settings.height), (800, 800));
+        assert_eq!(settings.sample_count, 256);
+        assert_eq!(settings.seed, SCENE_SEED);
+        assert_eq!(settings.output, PathBuf::from("render.png"));
+    }
+
+    #[test]
+    fn render_options() {
+        let settings = settings(&[
+            "render",
+            "--width",
+            "320",
+            "--height",
+            "240",
+            "--spp",
+            "10",
+            "--seed",
+            "1.5, -2",
+            "--output",
+            "out/image.exr",
+        ]);
+        assert_eq!((settings.width, settings.height), (320, 240));
+        assert_eq!(settings.sample_count, 10);
+        assert_eq!(settings.seed, [1.5, -2.0]);
+        assert_eq!(settings.output, PathBuf::from("out/image.exr"));
+    }
+
+    #[test]
+    fn invalid_options() {
+        for args in [
+            &["render", "--width"][..],
+            &["render", "--width", "0"],
+            &["render", "--height", "-5"],
+            &["render", "--spp", "0"],
+            &["render", "--spp", "many"],
+            &["render", "--seed", "1.5"],
+            &["render", "--seed", "a,b"],
+            &["render", "--samples", "4"],
+        ] {
+            assert!(parse(args).is_err(), "{:?} should be rejected", args);
+        }
+    }
+}
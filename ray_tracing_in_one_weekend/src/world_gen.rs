@@ -0,0 +1,130 @@
+use shader_engine::shader::UniformStruct;
+
+// Material types that need special handling, same as the defines in `hit_record.glsl`
+const DISCO: i32 = 3;
+const CHECKER_BOARD: i32 = 4;
+
+#[derive(Clone, Debug, Default, UniformStruct)]
+pub struct Material {
+    pub material_type: i32,
+    pub albedo: [f32; 3],
+    pub fuzz: f32,
+    pub refraction_index: f32,
+}
+
+#[derive(Clone, Debug, Default, UniformStruct)]
+pub struct Sphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub material: Material,
+}
+
+/// Same generator the shader used, the last sphere is always the ground.
+/// The materials are hashed from the positions, so they can differ from the GPU version in the last bits
+pub fn setup_scene(seed: [f32; 2], sphere_count: u32) -> Vec<Sphere> {
+    let mut spheres = (0..sphere_count)
+        .map(|i| random_sphere([seed[0], seed[1], i as f32], sphere_count))
+        .collect::<Vec<_>>();
+
+    // Grounddddd
+    if let Some(ground) = spheres.last_mut() {
+        *ground = Sphere {
+            center: [0.0, -1000.5, 0.0],
+            radius: 1000.0,
+            material: Material {
+                material_type: CHECKER_BOARD,
+                albedo: [0.5, 0.8, 0.2],
+                fuzz: 0.0,
+                refraction_index: 0.0,
+            },
+        };
+    }
+
+    spheres
+}
+
+fn random_material(seed: [f32; 3]) -> Material {
+    let material_type = (random(&seed) * 4.0) as i32;
+
+    let albedo = random_vec3(seed).map(|v| v.abs() * 0.9 + 0.1);
+    let mut fuzz = random(&seed) * 0.5;
+    let mut refraction_index = mix(1.1, 2.4, random(&seed));
+
+    if material_type == DISCO {
+        fuzz = ((random(&[seed[0], seed[1], seed[2], fuzz]) * 24.0) as i32) as f32 + 8.0;
+
+        // The rotation is packed into the refraction index, 10 bits per axis
+        let rot_x = (random(&[seed[0], seed[1], seed[2], refraction_index]) * 360.0) as i32;
+        let rot_y = (random(&[seed[0], seed[1], seed[2], rot_x as f32]) * 360.0) as i32;
+        let rot_z = (random(&[seed[0], seed[1], seed[2], rot_y as f32]) * 360.0) as i32;
+        refraction_index = (rot_x << 20 | rot_y << 10 | rot_z) as f32;
+    }
+
+    Material {
+        material_type,
+        albedo,
+        fuzz,
+        refraction_index,
+    }
+}
+
+fn random_sphere(seed: [f32; 3], sphere_count: u32) -> Sphere {
+    let grid_size = (sphere_count as f32).sqrt();
+    let seed_pos = [seed[2] / grid_size, glsl_mod(seed[2], grid_size)].map(|v| v - grid_size / 2.0);
+
+    let offset = random_vec3(seed);
+    let mut center = [
+        seed_pos[0] * 1.25 + offset[0] * 0.4,
+        offset[1] * 0.4,
+        seed_pos[1] * 1.25 + offset[2] * 0.4,
+    ];
+    let radius = mix(0.2, 0.5, random(&[0, 1, 2].map(|i| center[i] + seed[i])));
+    center[1] = radius - 0.5;
+
+    Sphere {
+        center,
+        radius,
+        material: random_material(center),
+    }
+}
+
+// Port of the hash based random in `utils/math.glsl`
+
+/// A single iteration of Bob Jenkins' One-At-A-Time hashing algorithm
+fn hash(mut x: u32) -> u32 {
+    x = x.wrapping_add(x << 10);
+    x ^= x >> 6;
+    x = x.wrapping_add(x << 3);
+    x ^= x >> 11;
+    x = x.wrapping_add(x << 15);
+    x
+}
+
+/// Pseudo-random value in half-open range [0:1], the compound hashes are folded from the last component
+fn random(values: &[f32]) -> f32 {
+    let (last, rest) = values.split_last().unwrap();
+    let hashed = rest
+        .iter()
+        .rev()
+        .fold(hash(last.to_bits()), |hashed, value| {
+            hash(value.to_bits() ^ hashed)
+        });
+
+    // Keep the mantissa and put it after 1.0, range [1:2]
+    f32::from_bits(hashed & 0x007F_FFFF | 0x3F80_0000) - 1.0
+}
+
+fn random_vec3(seed: [f32; 3]) -> [f32; 3] {
+    let x = random(&seed) * 2.0 - 1.0;
+    let y = random(&[seed[0], seed[1], seed[2], x]) * 2.0 - 1.0;
+    let z = random(&[seed[0], seed[1], seed[2], y]) * 2.0 - 1.0;
+    [x, y, z]
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+fn glsl_mod(x: f32, y: f32) -> f32 {
+    x - y * (x / y).floor()
+}
@@ -0,0 +1,338 @@
+use crate::shader::introspection::ActiveBlock;
+
+/// Packing rules of a block, `std140` for uniform blocks and `std430` for storage blocks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutRules {
+    Std140,
+    Std430,
+}
+
+impl LayoutRules {
+    /// Alignment of a struct or an array with the given element alignment,
+    /// std140 rounds them up to the alignment of a vec4
+    pub fn aggregate_alignment(self, alignment: usize) -> usize {
+        match self {
+            LayoutRules::Std140 => align_to(alignment, 16),
+            LayoutRules::Std430 => alignment,
+        }
+    }
+
+    /// Distance between the elements of an array
+    pub fn array_stride(self, element_size: usize, element_alignment: usize) -> usize {
+        align_to(element_size, self.aggregate_alignment(element_alignment))
+    }
+}
+
+/// A type that can be written into a uniform or a storage block, usually implemented with `#[derive(BlockLayout)]`.
+///
+/// Vectors are `[T; N]` and matrices are arrays of columns like `[[f32; 4]; 4]`, the same as the uniforms.
+/// Arrays of vectors, matrices and structs are `[T; N]`, arrays of scalars aren't supported since they would look
+/// like vectors
+pub trait BlockLayout: Sized {
+    /// Base alignment, in bytes
+    fn alignment(rules: LayoutRules) -> usize;
+
+    /// Size in bytes, including the padding at the end of structs and arrays
+    fn size(rules: LayoutRules) -> usize;
+
+    /// Writes the value at the start of `bytes`, padding is left untouched
+    fn write(&self, rules: LayoutRules, bytes: &mut [u8]);
+
+    /// Reads a value written with the same rules
+    fn read(rules: LayoutRules, bytes: &[u8]) -> Self;
+
+    /// Offsets of every member, with paths like `.color` or `[1].position` as the program names them
+    fn member_offsets(_rules: LayoutRules) -> Vec<(String, usize)> {
+        vec![(String::new(), 0)]
+    }
+}
+
+/// Types that can be the elements of a `[T; N]` in a block, everything but the scalars
+pub trait ArrayElement: BlockLayout {}
+
+/// Rounds the offset up to the next multiple of the alignment
+pub fn align_to(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment.max(1)) * alignment.max(1)
+}
+
+/// Checks the Rust layout of `T` against the offsets the program reports for the block
+pub(crate) fn validate_block<T: BlockLayout>(
+    block: &ActiveBlock,
+    rules: LayoutRules,
+) -> Result<(), String> {
+    let offsets = T::member_offsets(rules);
+    let mut problems = Vec::new();
+
+    let size = T::size(rules);
+    if size < block.data_size {
+        problems.push(format!(
+            "the block needs {} bytes but the Rust type has {}",
+            block.data_size, size
+        ));
+    }
+
+    for member in block.members.iter() {
+        let name = member_name(block, &member.name);
+        let path = format!(".{}", name);
+        check_offset(&offsets, name, &path, member.offset, &mut problems);
+    }
+
+    layout_result(block, problems)
+}
+
+/// Checks the Rust layout of `T` against a storage block made of a single runtime array like `T values[]`,
+/// only the first element of the array is reported by the program
+pub(crate) fn validate_storage_block<T: BlockLayout>(block: &ActiveBlock) -> Result<(), String> {
+    let rules = LayoutRules::Std430;
+    let offsets = T::member_offsets(rules);
+    let stride = rules.array_stride(T::size(rules), T::alignment(rules));
+    let mut problems = Vec::new();
+
+    for member in block.members.iter() {
+        let name = member_name(block, &member.name);
+        let Some((_, element_path)) = name.split_once("[0]") else {
+            problems.push(format!("\"{}\" is not part of the array", name));
+            continue;
+        };
+
+        if member.top_level_array_stride != 0 && member.top_level_array_stride != stride {
+            problems.push(format!(
+                "\"{}\" has a stride of {} but the Rust type has {}",
+                name, member.top_level_array_stride, stride
+            ));
+        }
+        check_offset(&offsets, name, element_path, member.offset, &mut problems);
+    }
+
+    layout_result(block, problems)
+}
+
+/// Members of blocks with an instance name are prefixed with the block name
+fn member_name<'a>(block: &ActiveBlock, name: &'a str) -> &'a str {
+    name.strip_prefix(&format!("{}.", block.name))
+        .unwrap_or(name)
+}
+
+fn check_offset(
+    offsets: &[(String, usize)],
+    name: &str,
+    path: &str,
+    expected: usize,
+    problems: &mut Vec<String>,
+) {
+    // Matrices are reported as a whole, the Rust side only knows their columns
+    let column_path = format!("{}[0]", path);
+    let offset = offsets
+        .iter()
+        .find(|(member_path, _)| *member_path == path || *member_path == column_path)
+        .map(|(_, offset)| *offset);
+
+    match offset {
+        Some(offset) if offset == expected => {}
+        Some(offset) => problems.push(format!(
+            "\"{}\" is at offset {} but the Rust type puts it at {}",
+            name, expected, offset
+        )),
+        None => problems.push(format!("\"{}\" has no matching field", name)),
+    }
+}
+
+fn layout_result(block: &ActiveBlock, problems: Vec<String>) -> Result<(), String> {
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Layout of block \"{}\" doesn't match: {}",
+            block.name,
+            problems.join(", ")
+        ))
+    }
+}
+
+macro_rules! impl_scalar_layout {
+    ($ty:ty, $size:expr, |$value:ident| $to_bytes:expr, |$bytes:ident| $from_bytes:expr) => {
+        impl BlockLayout for $ty {
+            fn alignment(_rules: LayoutRules) -> usize {
+                $size
+            }
+
+            fn size(_rules: LayoutRules) -> usize {
+                $size
+            }
+
+            fn write(&self, _rules: LayoutRules, bytes: &mut [u8]) {
+                let $value = *self;
+                bytes[..$size].copy_from_slice(&$to_bytes);
+            }
+
+            fn read(_rules: LayoutRules, bytes: &[u8]) -> Self {
+                let $bytes: [u8; $size] = bytes[..$size].try_into().unwrap();
+                $from_bytes
+            }
+        }
+
+        impl_vector_layout!($ty, $size, [2, 3, 4]);
+    };
+}
+
+macro_rules! impl_vector_layout {
+    ($ty:ty, $size:expr, [$($len:expr),+]) => {
+        $(
+            impl BlockLayout for [$ty; $len] {
+                // A vec3 is aligned like a vec4
+                fn alignment(_rules: LayoutRules) -> usize {
+                    if $len == 3 { 4 * $size } else { $len * $size }
+                }
+
+                fn size(_rules: LayoutRules) -> usize {
+                    $len * $size
+                }
+
+                fn write(&self, rules: LayoutRules, bytes: &mut [u8]) {
+                    for (index, value) in self.iter().enumerate() {
+                        value.write(rules, &mut bytes[index * $size..]);
+                    }
+                }
+
+                fn read(rules: LayoutRules, bytes: &[u8]) -> Self {
+                    std::array::from_fn(|index| <$ty>::read(rules, &bytes[index * $size..]))
+                }
+            }
+
+            impl ArrayElement for [$ty; $len] {}
+        )+
+    };
+}
+
+// Booleans take 4 bytes in blocks
+impl_scalar_layout!(
+    bool,
+    4,
+    |v| (v as u32).to_ne_bytes(),
+    |b| u32::from_ne_bytes(b) != 0
+);
+impl_scalar_layout!(i32, 4, |v| v.to_ne_bytes(), |b| i32::from_ne_bytes(b));
+impl_scalar_layout!(u32, 4, |v| v.to_ne_bytes(), |b| u32::from_ne_bytes(b));
+impl_scalar_layout!(f32, 4, |v| v.to_ne_bytes(), |b| f32::from_ne_bytes(b));
+impl_scalar_layout!(f64, 8, |v| v.to_ne_bytes(), |b| f64::from_ne_bytes(b));
+
+// Arrays, and matrices since a matCxR is laid out like an array of C column vectors
+impl<T: ArrayElement, const N: usize> BlockLayout for [T; N] {
+    fn alignment(rules: LayoutRules) -> usize {
+        rules.aggregate_alignment(T::alignment(rules))
+    }
+
+    fn size(rules: LayoutRules) -> usize {
+        rules.array_stride(T::size(rules), T::alignment(rules)) * N
+    }
+
+    fn write(&self, rules: LayoutRules, bytes: &mut [u8]) {
+        let stride = rules.array_stride(T::size(rules), T::alignment(rules));
+        for (index, element) in self.iter().enumerate() {
+            element.write(rules, &mut bytes[index * stride..]);
+        }
+    }
+
+    fn read(rules: LayoutRules, bytes: &[u8]) -> Self {
+        let stride = rules.array_stride(T::size(rules), T::alignment(rules));
+        std::array::from_fn(|index| T::read(rules, &bytes[index * stride..]))
+    }
+
+    fn member_offsets(rules: LayoutRules) -> Vec<(String, usize)> {
+        let stride = rules.array_stride(T::size(rules), T::alignment(rules));
+        let element_offsets = T::member_offsets(rules);
+        (0..N)
+            .flat_map(|index| {
+                element_offsets.iter().map(move |(path, offset)| {
+                    (format!("[{}]{}", index, path), index * stride + offset)
+                })
+            })
+            .collect()
+    }
+}
+
+impl<T: ArrayElement, const N: usize> ArrayElement for [T; N] {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTH: [LayoutRules; 2] = [LayoutRules::Std140, LayoutRules::Std430];
+
+    fn layout<T: BlockLayout>(rules: LayoutRules) -> (usize, usize) {
+        (T::alignment(rules), T::size(rules))
+    }
+
+    #[test]
+    fn scalars_and_vectors_are_the_same_in_both_rules() {
+        for rules in BOTH {
+            assert_eq!(layout::<f32>(rules), (4, 4));
+            assert_eq!(layout::<bool>(rules), (4, 4));
+            assert_eq!(layout::<f64>(rules), (8, 8));
+            assert_eq!(layout::<[f32; 2]>(rules), (8, 8));
+            assert_eq!(layout::<[f32; 3]>(rules), (16, 12));
+            assert_eq!(layout::<[f32; 4]>(rules), (16, 16));
+            assert_eq!(layout::<[f64; 3]>(rules), (32, 24));
+        }
+    }
+
+    #[test]
+    fn std140_rounds_arrays_up_to_a_vec4() {
+        let rules = LayoutRules::Std140;
+        assert_eq!(layout::<[[f32; 2]; 3]>(rules), (16, 48));
+        assert_eq!(layout::<[[f32; 3]; 2]>(rules), (16, 32));
+        // mat3
+        assert_eq!(layout::<[[f32; 3]; 3]>(rules), (16, 48));
+        // mat2
+        assert_eq!(layout::<[[f32; 2]; 2]>(rules), (16, 32));
+        assert_eq!(rules.array_stride(4, 4), 16);
+    }
+
+    #[test]
+    fn std430_packs_arrays_tightly() {
+        let rules = LayoutRules::Std430;
+        assert_eq!(layout::<[[f32; 2]; 3]>(rules), (8, 24));
+        assert_eq!(layout::<[[f32; 3]; 2]>(rules), (16, 32));
+        assert_eq!(layout::<[[f32; 3]; 3]>(rules), (16, 48));
+        assert_eq!(layout::<[[f32; 2]; 2]>(rules), (8, 16));
+        assert_eq!(rules.array_stride(4, 4), 4);
+    }
+
+    #[test]
+    fn array_members_are_at_their_stride() {
+        let offsets = <[[f32; 2]; 3]>::member_offsets(LayoutRules::Std140);
+        let expected = [("[0]", 0), ("[1]", 16), ("[2]", 32)];
+        assert_eq!(
+            offsets,
+            expected.map(|(path, offset)| (path.to_string(), offset))
+        );
+
+        let offsets = <[[[f32; 2]; 2]; 2]>::member_offsets(LayoutRules::Std430);
+        let expected = [("[0][0]", 0), ("[0][1]", 8), ("[1][0]", 16), ("[1][1]", 24)];
+        assert_eq!(
+            offsets,
+            expected.map(|(path, offset)| (path.to_string(), offset))
+        );
+    }
+
+    #[test]
+    fn values_read_back_the_same() {
+        let matrix = [[1.0f32, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]];
+        for rules in BOTH {
+            let mut bytes = vec![0xFF; <[[f32; 3]; 3]>::size(rules)];
+            matrix.write(rules, &mut bytes);
+            assert_eq!(<[[f32; 3]; 3]>::read(rules, &bytes), matrix);
+            // The padding after each column is left untouched
+            assert_eq!(bytes[12..16], [0xFF; 4]);
+        }
+    }
+
+    #[test]
+    fn align_to_rounds_up() {
+        assert_eq!(align_to(0, 16), 0);
+        assert_eq!(align_to(1, 16), 16);
+        assert_eq!(align_to(16, 16), 16);
+        assert_eq!(align_to(20, 8), 24);
+        assert_eq!(align_to(5, 0), 5);
+    }
+}
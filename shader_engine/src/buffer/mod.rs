@@ -0,0 +1,8 @@
+pub mod layout;
+mod storage_buffer;
+mod uniform_buffer;
+
+pub use crate::buffer::layout::{BlockLayout, LayoutRules};
+pub use crate::buffer::storage_buffer::{StorageBuffer, StorageMapping};
+pub use crate::buffer::uniform_buffer::UniformBuffer;
+pub use shader_engine_derive::BlockLayout;
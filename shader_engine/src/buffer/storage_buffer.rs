@@ -0,0 +1,279 @@
+use crate::buffer::layout::{BlockLayout, LayoutRules};
+use gl::types::{GLintptr, GLsizeiptr, GLuint};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::ptr;
+
+/// A shader storage buffer holding the runtime array of a block like `buffer Data { T values[]; }`,
+/// the elements are stored with the std430 layout.
+/// It grows when more elements are written, and can be read back after a dispatch
+pub struct StorageBuffer<T> {
+    id: GLuint,
+    len: usize,
+    capacity: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: BlockLayout> StorageBuffer<T> {
+    pub fn new() -> StorageBuffer<T> {
+        StorageBuffer::with_capacity(0)
+    }
+
+    /// Allocates room for `capacity` elements, the buffer is still empty
+    pub fn with_capacity(capacity: usize) -> StorageBuffer<T> {
+        StorageBuffer {
+            id: allocate(capacity * Self::stride()),
+            len: 0,
+            capacity,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn from_slice(values: &[T]) -> StorageBuffer<T> {
+        let mut buffer = StorageBuffer::with_capacity(values.len());
+        buffer.update(0, values);
+        buffer
+    }
+
+    /// Distance between two elements in the buffer, in bytes
+    pub fn stride() -> usize {
+        LayoutRules::Std430.array_stride(
+            T::size(LayoutRules::Std430),
+            T::alignment(LayoutRules::Std430),
+        )
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Replaces every element
+    pub fn set(&mut self, values: &[T]) {
+        self.len = 0;
+        self.update(0, values);
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.update(self.len, &[value]);
+    }
+
+    /// Writes the values starting from the element at `start`, only that range is uploaded.
+    /// Writing past the end makes the buffer longer
+    pub fn update(&mut self, start: usize, values: &[T]) {
+        assert!(
+            start <= self.len,
+            "Update starts at {} but the buffer has {} elements",
+            start,
+            self.len
+        );
+
+        let end = start + values.len();
+        self.reserve(end);
+        self.len = self.len.max(end);
+
+        let stride = Self::stride();
+        let mut bytes = vec![0u8; values.len() * stride];
+        for (index, value) in values.iter().enumerate() {
+            value.write(LayoutRules::Std430, &mut bytes[index * stride..]);
+        }
+
+        unsafe {
+            gl::NamedBufferSubData(
+                self.id,
+                (start * stride) as GLintptr,
+                bytes.len() as GLsizeiptr,
+                bytes.as_ptr() as *const _,
+            );
+        }
+    }
+
+    /// Shortens the buffer, the allocation is kept
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// Makes room for at least `capacity` elements, the current elements are copied on the GPU.
+    /// Growing creates a new buffer object, so it has to be bound again
+    pub fn reserve(&mut self, capacity: usize) {
+        if capacity <= self.capacity {
+            return;
+        }
+
+        let capacity = capacity.max(self.capacity * 2);
+        let id = allocate(capacity * Self::stride());
+        unsafe {
+            if self.len > 0 {
+                gl::CopyNamedBufferSubData(
+                    self.id,
+                    id,
+                    0,
+                    0,
+                    (self.len * Self::stride()) as GLsizeiptr,
+                );
+            }
+            gl::DeleteBuffers(1, &self.id);
+        }
+
+        self.id = id;
+        self.capacity = capacity;
+    }
+
+    /// Reads every element, usually after a dispatch wrote to the buffer
+    pub fn read_back(&self) -> Vec<T> {
+        self.read_range(0..self.len)
+    }
+
+    pub fn read_range(&self, range: Range<usize>) -> Vec<T> {
+        assert!(
+            range.end <= self.len,
+            "Range ends at {} but the buffer has {} elements",
+            range.end,
+            self.len
+        );
+        if range.is_empty() {
+            return Vec::new();
+        }
+
+        let stride = Self::stride();
+        let mut bytes = vec![0u8; range.len() * stride];
+        unsafe {
+            gl::GetNamedBufferSubData(
+                self.id,
+                (range.start * stride) as GLintptr,
+                bytes.len() as GLsizeiptr,
+                bytes.as_mut_ptr() as *mut _,
+            );
+        }
+
+        (0..range.len())
+            .map(|index| T::read(LayoutRules::Std430, &bytes[index * stride..]))
+            .collect()
+    }
+
+    /// Maps the elements into memory, so single elements can be read and written without copying the whole buffer.
+    /// The buffer is unmapped when the mapping is dropped
+    pub fn map(&mut self) -> Result<StorageMapping<'_, T>, String> {
+        let length = self.len * Self::stride();
+        let pointer = if length == 0 {
+            ptr::null_mut()
+        } else {
+            let pointer = unsafe {
+                gl::MapNamedBufferRange(
+                    self.id,
+                    0,
+                    length as GLsizeiptr,
+                    gl::MAP_READ_BIT | gl::MAP_WRITE_BIT,
+                ) as *mut u8
+            };
+            if pointer.is_null() {
+                return Err(format!("Failed to map storage buffer {}", self.id));
+            }
+            pointer
+        };
+
+        Ok(StorageMapping {
+            buffer: self,
+            pointer,
+        })
+    }
+
+    /// Binds the buffer to a storage block binding point
+    pub fn bind(&self, binding: GLuint) {
+        unsafe {
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding, self.id);
+        }
+    }
+}
+
+impl<T: BlockLayout> Default for StorageBuffer<T> {
+    fn default() -> Self {
+        StorageBuffer::new()
+    }
+}
+
+impl<T> Drop for StorageBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+/// Elements of a mapped StorageBuffer
+pub struct StorageMapping<'a, T: BlockLayout> {
+    buffer: &'a mut StorageBuffer<T>,
+    pointer: *mut u8,
+}
+
+impl<T: BlockLayout> StorageMapping<'_, T> {
+    pub fn len(&self) -> usize {
+        self.buffer.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> T {
+        T::read(LayoutRules::Std430, self.element(index))
+    }
+
+    pub fn set(&mut self, index: usize, value: &T) {
+        value.write(LayoutRules::Std430, self.element_mut(index));
+    }
+
+    fn element(&self, index: usize) -> &[u8] {
+        self.check_index(index);
+        let stride = StorageBuffer::<T>::stride();
+        // SAFETY: The mapping covers every element and lives as long as the borrow of the buffer
+        unsafe { std::slice::from_raw_parts(self.pointer.add(index * stride), stride) }
+    }
+
+    fn element_mut(&mut self, index: usize) -> &mut [u8] {
+        self.check_index(index);
+        let stride = StorageBuffer::<T>::stride();
+        // SAFETY: Same as `element`, and the mapping is borrowed mutably so nothing else reads the element
+        unsafe { std::slice::from_raw_parts_mut(self.pointer.add(index * stride), stride) }
+    }
+
+    fn check_index(&self, index: usize) {
+        assert!(
+            index < self.buffer.len,
+            "Index {} is out of {} elements",
+            index,
+            self.buffer.len
+        );
+    }
+}
+
+impl<T: BlockLayout> Drop for StorageMapping<'_, T> {
+    fn drop(&mut self) {
+        if !self.pointer.is_null() {
+            unsafe {
+                gl::UnmapNamedBuffer(self.buffer.id);
+            }
+        }
+    }
+}
+
+/// Creates a buffer object with room for `size` bytes
+fn allocate(size: usize) -> GLuint {
+    let mut id = 0;
+    unsafe {
+        gl::CreateBuffers(1, &mut id);
+        gl::NamedBufferData(id, size as GLsizeiptr, ptr::null(), gl::DYNAMIC_COPY);
+    }
+    id
+}
@@ -0,0 +1,76 @@
+use crate::buffer::layout::{BlockLayout, LayoutRules};
+use crate::shader::bindable::Bindable;
+use gl::types::{GLintptr, GLsizeiptr, GLuint};
+use std::ptr;
+
+/// A uniform buffer object holding a single `T` with the std140 layout.
+/// Changes through the Bindable are uploaded the next time the buffer is bound
+pub struct UniformBuffer<T> {
+    id: GLuint,
+    bind: Bindable<T>,
+}
+
+impl<T: BlockLayout> UniformBuffer<T> {
+    pub fn new(initial: T) -> UniformBuffer<T> {
+        let mut id = 0;
+        unsafe {
+            gl::CreateBuffers(1, &mut id);
+            gl::NamedBufferData(
+                id,
+                T::size(LayoutRules::Std140) as GLsizeiptr,
+                ptr::null(),
+                gl::DYNAMIC_DRAW,
+            );
+        }
+
+        UniformBuffer {
+            id,
+            bind: Bindable::new(initial),
+        }
+    }
+
+    pub fn get_bind(&mut self) -> &mut Bindable<T> {
+        &mut self.bind
+    }
+
+    pub fn get_value(&self) -> &T {
+        self.bind.get()
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+
+    /// Binds the buffer to a uniform block binding point, uploading the value first if it changed
+    pub fn bind(&mut self, binding: GLuint) {
+        if self.bind.is_dirty() {
+            self.upload();
+            self.bind.clear_dirty();
+        }
+
+        unsafe {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, binding, self.id);
+        }
+    }
+
+    fn upload(&self) {
+        let mut bytes = vec![0u8; T::size(LayoutRules::Std140)];
+        self.bind.get().write(LayoutRules::Std140, &mut bytes);
+        unsafe {
+            gl::NamedBufferSubData(
+                self.id,
+                0 as GLintptr,
+                bytes.len() as GLsizeiptr,
+                bytes.as_ptr() as *const _,
+            );
+        }
+    }
+}
+
+impl<T> Drop for UniformBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
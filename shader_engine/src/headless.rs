@@ -0,0 +1,188 @@
+use crate::screenshot::capture;
+use gl::types::{GLsizei, GLuint};
+use khronos_egl as egl;
+use std::os::raw;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// `EGL_PLATFORM_SURFACELESS_MESA`, a display that needs no window system
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+/// Context versions tried in order, compute shaders and the DSA functions need at least 4.5
+const CONTEXT_VERSIONS: [(egl::Int, egl::Int); 2] = [(4, 6), (4, 5)];
+
+/// A GL context without a window, created through EGL so it works on machines without a display,
+/// including Mesa's software renderer.
+/// There is no default framebuffer, so draws go to an offscreen framebuffer of the same size instead
+pub struct HeadlessContext {
+    egl: egl::DynamicInstance<egl::EGL1_4>,
+    display: egl::Display,
+    context: egl::Context,
+
+    pub framebuffer: GLuint,
+    color_buffer: GLuint,
+    depth_buffer: GLuint,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl HeadlessContext {
+    pub fn new(width: i32, height: i32) -> Result<HeadlessContext, String> {
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_4>::load_required() }
+            .map_err(|e| format!("Failed to load libEGL: {}", e))?;
+
+        // Surfaceless needs EGL 1.5, older versions fall back to the default display
+        let display = match egl.upcast::<egl::EGL1_5>() {
+            Some(egl) => unsafe {
+                egl.get_platform_display(
+                    PLATFORM_SURFACELESS_MESA,
+                    egl::DEFAULT_DISPLAY,
+                    &[egl::ATTRIB_NONE],
+                )
+                .map_err(|e| format!("Failed to get the surfaceless display: {}", e))?
+            },
+            None => unsafe { egl.get_display(egl::DEFAULT_DISPLAY) }
+                .ok_or("Failed to get the default display")?,
+        };
+
+        egl.initialize(display)
+            .map_err(|e| format!("Failed to initialize EGL: {}", e))?;
+        egl.bind_api(egl::OPENGL_API)
+            .map_err(|e| format!("Failed to bind the OpenGL API: {}", e))?;
+
+        let config = egl
+            .choose_first_config(
+                display,
+                &[
+                    egl::RENDERABLE_TYPE,
+                    egl::OPENGL_BIT,
+                    egl::SURFACE_TYPE,
+                    0,
+                    egl::NONE,
+                ],
+            )
+            .map_err(|e| format!("Failed to choose a config: {}", e))?
+            .ok_or("No config supports OpenGL")?;
+
+        let context = CONTEXT_VERSIONS
+            .iter()
+            .find_map(|(major, minor)| {
+                let attributes = [
+                    egl::CONTEXT_MAJOR_VERSION,
+                    *major,
+                    egl::CONTEXT_MINOR_VERSION,
+                    *minor,
+                    egl::CONTEXT_OPENGL_PROFILE_MASK,
+                    egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+                    egl::NONE,
+                ];
+                egl.create_context(display, config, None, &attributes).ok()
+            })
+            .ok_or("Failed to create an OpenGL 4.5 context")?;
+
+        egl.make_current(display, None, None, Some(context))
+            .map_err(|e| format!("Failed to make the context current: {}", e))?;
+
+        gl::load_with(|name| {
+            egl.get_proc_address(name)
+                .map_or(ptr::null(), |function| function as *const raw::c_void)
+        });
+
+        let mut headless = HeadlessContext {
+            egl,
+            display,
+            context,
+
+            framebuffer: 0,
+            color_buffer: 0,
+            depth_buffer: 0,
+            width,
+            height,
+        };
+        headless.create_framebuffer();
+
+        unsafe {
+            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+        }
+
+        Ok(headless)
+    }
+
+    pub(crate) fn resize(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+
+        self.delete_framebuffer();
+        self.create_framebuffer();
+    }
+
+    /// Saves the offscreen framebuffer as a timestamped PNG in the directory, like `ShaderWindow::screenshot`
+    pub fn screenshot<P: AsRef<Path>>(&self, directory: P) -> Result<PathBuf, String> {
+        capture(
+            self.framebuffer,
+            gl::COLOR_ATTACHMENT0,
+            self.width,
+            self.height,
+            directory.as_ref(),
+        )
+    }
+
+    /// Waits for the frame to finish, there is no buffer to swap
+    pub(crate) fn finish_frame(&mut self) {
+        unsafe {
+            gl::Finish();
+        }
+    }
+
+    /// Creates the framebuffer standing in for the window, and binds it with a matching viewport
+    fn create_framebuffer(&mut self) {
+        unsafe {
+            gl::CreateRenderbuffers(1, &mut self.color_buffer);
+            gl::NamedRenderbufferStorage(self.color_buffer, gl::RGBA8, self.width, self.height);
+
+            gl::CreateRenderbuffers(1, &mut self.depth_buffer);
+            gl::NamedRenderbufferStorage(
+                self.depth_buffer,
+                gl::DEPTH24_STENCIL8,
+                self.width,
+                self.height,
+            );
+
+            gl::CreateFramebuffers(1, &mut self.framebuffer);
+            gl::NamedFramebufferRenderbuffer(
+                self.framebuffer,
+                gl::COLOR_ATTACHMENT0,
+                gl::RENDERBUFFER,
+                self.color_buffer,
+            );
+            gl::NamedFramebufferRenderbuffer(
+                self.framebuffer,
+                gl::DEPTH_STENCIL_ATTACHMENT,
+                gl::RENDERBUFFER,
+                self.depth_buffer,
+            );
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Viewport(0, 0, self.width as GLsizei, self.height as GLsizei);
+        }
+    }
+
+    fn delete_framebuffer(&mut self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteRenderbuffers(1, &self.color_buffer);
+            gl::DeleteRenderbuffers(1, &self.depth_buffer);
+        }
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        self.delete_framebuffer();
+
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_context(self.display, self.context);
+        let _ = self.egl.terminate(self.display);
+    }
+}
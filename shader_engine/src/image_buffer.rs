@@ -1,4 +1,62 @@
+use crate::image_export::{save_exr, save_hdr, save_png, ExportOptions};
 use gl::types::{GLenum, GLint, GLsizei, GLuint};
+use std::path::Path;
+
+/// Type of a single channel read back from an image, GL converts between the float and normalized formats.
+/// Integer formats like R32UI can only be read into the integer types
+pub trait PixelComponent: Copy + Default {
+    const GL_TYPE: GLenum;
+    const IS_FLOAT: bool;
+}
+
+macro_rules! impl_pixel_component {
+    ($ty:ty, $gl_type:expr, $is_float:expr) => {
+        impl PixelComponent for $ty {
+            const GL_TYPE: GLenum = $gl_type;
+            const IS_FLOAT: bool = $is_float;
+        }
+    };
+}
+
+impl_pixel_component!(u8, gl::UNSIGNED_BYTE, false);
+impl_pixel_component!(i8, gl::BYTE, false);
+impl_pixel_component!(u16, gl::UNSIGNED_SHORT, false);
+impl_pixel_component!(i16, gl::SHORT, false);
+impl_pixel_component!(u32, gl::UNSIGNED_INT, false);
+impl_pixel_component!(i32, gl::INT, false);
+impl_pixel_component!(f32, gl::FLOAT, true);
+
+/// Pixel format and channel count of an internal format, and whether it is an integer format
+fn pixel_format(internal_format: GLenum) -> Option<(GLenum, usize, bool)> {
+    let format = match internal_format {
+        gl::R8 | gl::R8_SNORM | gl::R16 | gl::R16_SNORM | gl::R16F | gl::R32F => {
+            (gl::RED, 1, false)
+        }
+        gl::RG8 | gl::RG8_SNORM | gl::RG16 | gl::RG16_SNORM | gl::RG16F | gl::RG32F => {
+            (gl::RG, 2, false)
+        }
+        gl::RGB8 | gl::SRGB8 | gl::RGB16F | gl::RGB32F | gl::R11F_G11F_B10F => (gl::RGB, 3, false),
+        gl::RGBA8
+        | gl::SRGB8_ALPHA8
+        | gl::RGBA8_SNORM
+        | gl::RGBA16
+        | gl::RGBA16F
+        | gl::RGBA32F
+        | gl::RGB10_A2 => (gl::RGBA, 4, false),
+
+        gl::R8I | gl::R8UI | gl::R16I | gl::R16UI | gl::R32I | gl::R32UI => {
+            (gl::RED_INTEGER, 1, true)
+        }
+        gl::RG8I | gl::RG8UI | gl::RG16I | gl::RG16UI | gl::RG32I | gl::RG32UI => {
+            (gl::RG_INTEGER, 2, true)
+        }
+        gl::RGBA8I | gl::RGBA8UI | gl::RGBA16I | gl::RGBA16UI | gl::RGBA32I | gl::RGBA32UI => {
+            (gl::RGBA_INTEGER, 4, true)
+        }
+        _ => return None,
+    };
+    Some(format)
+}
 
 macro_rules! image_texture_type {
     (
@@ -60,6 +118,14 @@ macro_rules! image_texture_type {
                 $name::new($($dim,)+ self.access, self.internal_format, self.format)
             }
 
+            /// Fills the image with zeros, new images start with undefined contents
+            pub fn clear(&self) {
+                let format = pixel_format(self.internal_format).map_or(gl::RGBA, |(format, _, _)| format);
+                unsafe {
+                    gl::ClearTexImage(self.texture_id, 0, format, gl::UNSIGNED_BYTE, std::ptr::null());
+                }
+            }
+
             pub fn bind_as_image(&self, unit: GLuint) {
                 unsafe {
                     gl::BindImageTexture(
@@ -80,6 +146,47 @@ macro_rules! image_texture_type {
                     gl::BindTexture($gl_target, self.texture_id);
                 }
             }
+
+            /// Channels per pixel, like 4 for RGBA32F
+            pub fn channels(&self) -> usize {
+                pixel_format(self.internal_format).map_or(0, |(_, channels, _)| channels)
+            }
+
+            /// Reads every pixel into a buffer of `T` channels, rows go from the bottom like GL stores them.
+            /// An RGBA32F image gives 4 `f32` per pixel, an RGBA8 image read as `u8` gives 4 bytes
+            pub fn read_pixels<T: PixelComponent>(&self) -> Result<Vec<T>, String> {
+                let Some((format, channels, is_integer)) = pixel_format(self.internal_format) else {
+                    return Err(format!("Internal format 0x{:04X} can't be read back", self.internal_format));
+                };
+                if is_integer && T::IS_FLOAT {
+                    return Err(format!(
+                        "Internal format 0x{:04X} is an integer format, it can't be read as floats",
+                        self.internal_format
+                    ));
+                }
+
+                let length = 1 $(* self.$dim as usize)+ * channels;
+                let mut pixels = vec![T::default(); length];
+                unsafe {
+                    // Compute shaders don't always end with a barrier that covers reading the image back
+                    gl::MemoryBarrier(gl::TEXTURE_UPDATE_BARRIER_BIT);
+
+                    let mut alignment = 0;
+                    gl::GetIntegerv(gl::PACK_ALIGNMENT, &mut alignment);
+                    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+                    gl::GetTextureImage(
+                        self.texture_id,
+                        0,
+                        format,
+                        T::GL_TYPE,
+                        (length * size_of::<T>()) as GLsizei,
+                        pixels.as_mut_ptr() as *mut _,
+                    );
+                    gl::PixelStorei(gl::PACK_ALIGNMENT, alignment);
+                }
+
+                Ok(pixels)
+            }
         }
     };
 }
@@ -89,3 +196,42 @@ image_texture_type!(Image2D, gl::TEXTURE_2D, TextureStorage2D, [width, height]);
 image_texture_type!(Image3D, gl::TEXTURE_3D, TextureStorage3D, [
     width, height, depth
 ]);
+
+// Only 2D images can be saved as image files
+impl Image2D {
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, options: &ExportOptions) -> Result<(), String> {
+        let pixels = self.read_pixels::<f32>()?;
+        save_png(
+            path,
+            self.width as u32,
+            self.height as u32,
+            self.channels(),
+            &pixels,
+            options,
+        )
+    }
+
+    pub fn save_hdr<P: AsRef<Path>>(&self, path: P, options: &ExportOptions) -> Result<(), String> {
+        let pixels = self.read_pixels::<f32>()?;
+        save_hdr(
+            path,
+            self.width as u32,
+            self.height as u32,
+            self.channels(),
+            &pixels,
+            options,
+        )
+    }
+
+    pub fn save_exr<P: AsRef<Path>>(&self, path: P, options: &ExportOptions) -> Result<(), String> {
+        let pixels = self.read_pixels::<f32>()?;
+        save_exr(
+            path,
+            self.width as u32,
+            self.height as u32,
+            self.channels(),
+            &pixels,
+            options,
+        )
+    }
+}
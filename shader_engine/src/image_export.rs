@@ -0,0 +1,285 @@
+use image::{ImageFormat, Rgb32FImage, RgbImage, Rgba32FImage, RgbaImage};
+use std::path::Path;
+
+/// Curve that brings HDR values into the [0:1] range before they are written to an 8-bit file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapping {
+    /// Values are only clamped
+    #[default]
+    None,
+    /// `v / (1 + v)`
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+/// How the pixels are transformed before they are saved, in the order exposure, tone mapping, gamma.
+/// Alpha is saved as it is. The defaults keep the values unchanged, for images that are already display ready
+#[derive(Clone, Copy, Debug)]
+pub struct ExportOptions {
+    pub(crate) exposure: f32,
+    pub(crate) tone_mapping: ToneMapping,
+    pub(crate) gamma: f32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            exposure: 1.0,
+            tone_mapping: ToneMapping::None,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn new() -> ExportOptions {
+        ExportOptions::default()
+    }
+
+    /// Multiplies the colors before the tone mapping
+    pub fn exposure(mut self, exposure: f32) -> ExportOptions {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn tone_mapping(mut self, tone_mapping: ToneMapping) -> ExportOptions {
+        self.tone_mapping = tone_mapping;
+        self
+    }
+
+    /// Encodes the colors with `v^(1 / gamma)`, 2.2 for linear images going to a PNG
+    pub fn gamma(mut self, gamma: f32) -> ExportOptions {
+        self.gamma = gamma;
+        self
+    }
+
+    fn apply(&self, value: f32) -> f32 {
+        let value = value * self.exposure;
+        let value = match self.tone_mapping {
+            ToneMapping::None => value,
+            ToneMapping::Reinhard => value / (1.0 + value),
+            ToneMapping::Aces => {
+                let mapped =
+                    (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14);
+                mapped.clamp(0.0, 1.0)
+            }
+        };
+
+        if self.gamma == 1.0 {
+            value
+        } else {
+            value.max(0.0).powf(1.0 / self.gamma)
+        }
+    }
+}
+
+/// Saves the pixels as an 8-bit PNG, RGBA when there are 4 channels and RGB otherwise
+pub fn save_png<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    channels: usize,
+    pixels: &[f32],
+    options: &ExportOptions,
+) -> Result<(), String> {
+    let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let rows = top_down_rgba(width, height, channels, pixels, options)?;
+
+    if channels == 4 {
+        let data = rows.into_iter().map(to_byte).collect();
+        let image = RgbaImage::from_raw(width, height, data).unwrap();
+        save(image.save_with_format(&path, ImageFormat::Png), &path)
+    } else {
+        let data = without_alpha(rows).map(to_byte).collect();
+        let image = RgbImage::from_raw(width, height, data).unwrap();
+        save(image.save_with_format(&path, ImageFormat::Png), &path)
+    }
+}
+
+/// Saves the pixels as a Radiance HDR file, which has no alpha
+pub fn save_hdr<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    channels: usize,
+    pixels: &[f32],
+    options: &ExportOptions,
+) -> Result<(), String> {
+    let rows = top_down_rgba(width, height, channels, pixels, options)?;
+    let image = Rgb32FImage::from_raw(width, height, without_alpha(rows).collect()).unwrap();
+    save(image.save_with_format(&path, ImageFormat::Hdr), &path)
+}
+
+/// Saves the pixels as a 32-bit float OpenEXR file
+pub fn save_exr<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    channels: usize,
+    pixels: &[f32],
+    options: &ExportOptions,
+) -> Result<(), String> {
+    let rows = top_down_rgba(width, height, channels, pixels, options)?;
+    let image = Rgba32FImage::from_raw(width, height, rows).unwrap();
+    save(image.save_with_format(&path, ImageFormat::OpenExr), &path)
+}
+
+/// Expands the pixels to RGBA with the options applied, GL stores the rows from the bottom so they are flipped.
+/// Single channel images become gray, missing channels are 0 and missing alpha is 1
+fn top_down_rgba(
+    width: u32,
+    height: u32,
+    channels: usize,
+    pixels: &[f32],
+    options: &ExportOptions,
+) -> Result<Vec<f32>, String> {
+    if !(1..=4).contains(&channels) {
+        return Err(format!("Images have 1 to 4 channels, got {}", channels));
+    }
+    let expected = width as usize * height as usize * channels;
+    if pixels.len() != expected {
+        return Err(format!(
+            "Pixel count doesn't match the size, {}x{} with {} channels needs {} values but got {}",
+            width,
+            height,
+            channels,
+            expected,
+            pixels.len()
+        ));
+    }
+    // Chunks of 0 values can't be made, and there is nothing to flip anyway
+    if pixels.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let row_length = width as usize * channels;
+    let rows = pixels
+        .chunks_exact(row_length)
+        .rev()
+        .flat_map(|row| row.chunks_exact(channels))
+        .flat_map(|pixel| {
+            let color = match channels {
+                1 => [pixel[0], pixel[0], pixel[0]],
+                2 => [pixel[0], pixel[1], 0.0],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            };
+            let alpha = if channels == 4 { pixel[3] } else { 1.0 };
+
+            let [r, g, b] = color.map(|value| options.apply(value));
+            [r, g, b, alpha]
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn without_alpha(rgba: Vec<f32>) -> impl Iterator<Item = f32> {
+    rgba.into_iter()
+        .enumerate()
+        .filter(|(index, _)| index % 4 != 3)
+        .map(|(_, value)| value)
+}
+
+fn save<P: AsRef<Path>>(result: image::ImageResult<()>, path: P) -> Result<(), String> {
+    result.map_err(|e| format!("Failed to save {}: {}", path.as_ref().display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn rows_are_flipped_and_expanded_to_rgba() {
+        let options = ExportOptions::new();
+        // Bottom row first, like GL returns them
+        let rgb = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        assert_eq!(
+            top_down_rgba(1, 2, 3, &rgb, &options).unwrap(),
+            [4.0, 5.0, 6.0, 1.0, 1.0, 2.0, 3.0, 1.0]
+        );
+
+        let gray = [0.25, 0.5];
+        assert_eq!(
+            top_down_rgba(2, 1, 1, &gray, &options).unwrap(),
+            [0.25, 0.25, 0.25, 1.0, 0.5, 0.5, 0.5, 1.0]
+        );
+
+        let red_green = [0.1, 0.2];
+        assert_eq!(
+            top_down_rgba(1, 1, 2, &red_green, &options).unwrap(),
+            [0.1, 0.2, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn alpha_is_kept_as_it_is() {
+        let options = ExportOptions::new().exposure(2.0).gamma(2.2);
+        let rgba = [0.0, 0.0, 0.0, 0.5];
+        assert_eq!(top_down_rgba(1, 1, 4, &rgba, &options).unwrap()[3], 0.5);
+    }
+
+    #[test]
+    fn pixel_count_must_match_the_size() {
+        let error = top_down_rgba(2, 2, 3, &[0.0; 9], &ExportOptions::new()).unwrap_err();
+        assert!(
+            error.starts_with("Pixel count doesn't match the size"),
+            "{}",
+            error
+        );
+
+        let path = std::env::temp_dir().join("shader_engine_pixel_count.png");
+        assert!(save_png(&path, 2, 2, 3, &[0.0; 9], &ExportOptions::new()).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn channel_count_must_be_1_to_4() {
+        let options = ExportOptions::new();
+        assert!(top_down_rgba(1, 1, 0, &[], &options).is_err());
+        assert!(top_down_rgba(1, 1, 5, &[0.0; 5], &options).is_err());
+        assert_eq!(top_down_rgba(0, 3, 4, &[], &options), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn defaults_keep_the_values() {
+        let options = ExportOptions::new();
+        for value in [-1.0, 0.0, 0.5, 4.0] {
+            assert_eq!(options.apply(value), value);
+        }
+    }
+
+    #[test]
+    fn tone_mapping_curves() {
+        let reinhard = ExportOptions::new().tone_mapping(ToneMapping::Reinhard);
+        assert_close(reinhard.apply(0.0), 0.0);
+        assert_close(reinhard.apply(1.0), 0.5);
+        assert_close(reinhard.apply(3.0), 0.75);
+
+        let aces = ExportOptions::new().tone_mapping(ToneMapping::Aces);
+        assert_close(aces.apply(0.0), 0.0);
+        assert_close(aces.apply(1.0), 2.54 / 3.16);
+        assert_close(aces.apply(1000.0), 1.0);
+        assert!(aces.apply(0.18) > 0.0 && aces.apply(0.18) < 0.3);
+    }
+
+    #[test]
+    fn exposure_comes_before_the_tone_mapping_and_gamma_after() {
+        let options = ExportOptions::new()
+            .exposure(2.0)
+            .tone_mapping(ToneMapping::Reinhard)
+            .gamma(2.0);
+        // 0.5 * 2 = 1, mapped to 0.5, then encoded to sqrt(0.5)
+        assert_close(options.apply(0.5), 0.5f32.sqrt());
+        // Negative values can't be gamma encoded
+        assert_close(ExportOptions::new().gamma(2.2).apply(-1.0), 0.0);
+    }
+}
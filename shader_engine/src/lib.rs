@@ -1,11 +1,24 @@
+use crate::headless::HeadlessContext;
+use crate::recorder::FrameRecorder;
+use crate::screenshot::SCREENSHOT_DIRECTORY;
 use crate::timer::Timer;
 use crate::window_manager::{ShaderWindow, ShaderWindowBuilder};
-use gl::types::GLsizei;
+use gl::types::{GLenum, GLsizei, GLuint};
+use sdl2::event::{Event, EventWatch};
+use sdl2::keyboard::Keycode;
 use sdl2::video::WindowBuildError;
-use sdl2::EventPump;
+use sdl2::{EventPump, EventSubsystem, Sdl};
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
+pub mod buffer;
+pub mod headless;
 pub mod image_buffer;
+pub mod image_export;
 pub mod raw_model;
+pub mod recorder;
+pub mod screenshot;
 pub mod shader;
 pub mod timer;
 pub mod utils;
@@ -17,32 +30,86 @@ pub enum MainLoopResult {
     Resize(i32, i32),
 }
 
+/// Watches the events for the screenshot key, the subsystem is kept so SDL doesn't drop the watch
+type ScreenshotKeyWatch = (EventWatch<'static, Box<dyn FnMut(Event)>>, EventSubsystem);
+
 pub struct ShaderEngine {
     window_created: bool,
+    /// None for headless engines, they have no events so SDL is never started
+    sdl: Option<Sdl>,
+    event_pump: Option<EventPump>,
+
+    /// Set by `request_screenshot` and the screenshot key, the capture happens at the end of the frame
+    screenshot_requested: Rc<Cell<bool>>,
+    screenshot_key: Option<ScreenshotKeyWatch>,
+    recorder: Option<FrameRecorder>,
+
+    /// `set_loop` stops after this many frames when set
+    frame_limit: Option<u64>,
+    frame_count: u64,
+    elapsed_time: f64,
 
     total_running_timer: Timer,
     inner_timer: Timer,
 
     pub window_manager: Option<ShaderWindow>,
-    pub window_builder: ShaderWindowBuilder,
+    /// None for headless engines
+    pub window_builder: Option<ShaderWindowBuilder>,
+    pub headless: Option<HeadlessContext>,
 }
 
 impl ShaderEngine {
     pub fn create_window(title: &str, width: i32, height: i32) -> Result<ShaderEngine, String> {
-        let builder = ShaderWindow::get_builder(title, width, height)?;
-        Ok(Self {
+        let sdl = sdl2::init()?;
+        let builder = ShaderWindow::get_builder(&sdl, title, width, height)?;
+        Ok(Self::new(Some(sdl), Some(builder), None))
+    }
+
+    /// Creates an engine without a window, the GL context comes from EGL and draws go to an offscreen framebuffer.
+    /// Works on machines without a display or a GPU, through Mesa's software renderer.
+    /// SDL isn't used, so every thread can have its own headless engine, like the tests of a crate do.
+    /// The frames are run with `set_headless_loop` and `run_headless_frame`
+    pub fn create_headless(width: i32, height: i32) -> Result<ShaderEngine, String> {
+        let headless = HeadlessContext::new(width, height)?;
+
+        let mut engine = Self::new(None, None, Some(headless));
+        engine.window_created = true;
+        Ok(engine)
+    }
+
+    fn new(
+        sdl: Option<Sdl>,
+        window_builder: Option<ShaderWindowBuilder>,
+        headless: Option<HeadlessContext>,
+    ) -> ShaderEngine {
+        ShaderEngine {
             window_created: false,
+            sdl,
+            event_pump: None,
+
+            screenshot_requested: Rc::new(Cell::new(false)),
+            screenshot_key: None,
+            recorder: None,
+
+            frame_limit: None,
+            frame_count: 0,
+            elapsed_time: 0.0,
 
             total_running_timer: Timer::new(),
             inner_timer: Timer::new(),
 
             window_manager: None,
-            window_builder: builder,
-        })
+            window_builder,
+            headless,
+        }
     }
 
+    /// Builds the window, headless engines are ready from the start so nothing happens for them
     pub fn finalize_window(&mut self) -> Result<(), WindowBuildError> {
-        let screen_manager = self.window_builder.build_as_manager()?;
+        let Some(window_builder) = self.window_builder.as_mut() else {
+            return Ok(());
+        };
+        let screen_manager = window_builder.build_as_manager()?;
 
         self.window_created = true;
         self.window_manager = Some(screen_manager);
@@ -50,43 +117,223 @@ impl ShaderEngine {
         Ok(())
     }
 
+    pub fn is_headless(&self) -> bool {
+        self.headless.is_some()
+    }
+
+    /// Makes `set_loop` return after the given number of frames, None runs until the loop quits
+    pub fn set_frame_limit(&mut self, frame_limit: Option<u64>) {
+        self.frame_limit = frame_limit;
+    }
+
+    /// Number of frames run so far
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
     pub fn set_loop<F>(&mut self, mut loop_function: F)
     where
         F: FnMut(&mut EventPump, f64) -> MainLoopResult,
     {
-        if !self.window_created {
-            panic!("Window not created, call `finalize_window` first");
-        }
+        self.run_loop(|engine| engine.run_frame(&mut loop_function));
+    }
+
+    /// Same as `set_loop` for headless engines, which have no events
+    pub fn set_headless_loop<F>(&mut self, mut loop_function: F)
+    where
+        F: FnMut(f64) -> MainLoopResult,
+    {
+        self.run_loop(|engine| engine.run_headless_frame(&mut loop_function));
+    }
 
+    fn run_loop(&mut self, mut run_frame: impl FnMut(&mut ShaderEngine) -> MainLoopResult) {
         // Start the timer
         self.total_running_timer.update();
-        let mut elapsed_time = 0.0;
 
         // Start the main loop
-        let mut event_pump = self.window_builder.sdl.event_pump().unwrap();
-        loop {
-            // Start the inner timer
-            self.inner_timer.update();
-
-            // Clear color bit
-            unsafe {
-                gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+        while self
+            .frame_limit
+            .is_none_or(|frame_limit| self.frame_count < frame_limit)
+        {
+            if let MainLoopResult::Quit = run_frame(self) {
+                break;
             }
+        }
+    }
+
+    /// Runs a single frame of the loop, so the caller can drive the frames itself
+    pub fn run_frame<F>(&mut self, loop_function: F) -> MainLoopResult
+    where
+        F: FnOnce(&mut EventPump, f64) -> MainLoopResult,
+    {
+        self.begin_frame();
 
-            // Update the loop function
-            let result = loop_function(&mut event_pump, elapsed_time);
+        // Update the loop function
+        let Some(sdl) = self.sdl.as_ref() else {
+            panic!("Headless engines have no events, use `run_headless_frame`");
+        };
+        let event_pump = self
+            .event_pump
+            .get_or_insert_with(|| sdl.event_pump().unwrap());
+        let result = loop_function(event_pump, self.elapsed_time);
+
+        self.end_frame(result)
+    }
+
+    /// Same as `run_frame` for headless engines, which have no events
+    pub fn run_headless_frame<F>(&mut self, loop_function: F) -> MainLoopResult
+    where
+        F: FnOnce(f64) -> MainLoopResult,
+    {
+        self.begin_frame();
+        let result = loop_function(self.elapsed_time);
+        self.end_frame(result)
+    }
+
+    fn begin_frame(&mut self) {
+        if !self.window_created {
+            panic!("Window not created, call `finalize_window` first");
+        }
 
-            // Handle the result
-            match result {
-                MainLoopResult::Quit => break,
-                MainLoopResult::Continue => self.window_manager.as_mut().unwrap().swap_window(),
-                MainLoopResult::Resize(width, height) => {
-                    self.resize(width, height);
+        // Start the inner timer
+        self.inner_timer.update();
+
+        // Clear color bit
+        unsafe {
+            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+        }
+    }
+
+    fn end_frame(&mut self, result: MainLoopResult) -> MainLoopResult {
+        // Handle the result
+        match result {
+            MainLoopResult::Quit => {}
+            MainLoopResult::Continue => {
+                // The back buffer is only valid until it is swapped
+                if self.screenshot_requested.take() {
+                    match self.screenshot(SCREENSHOT_DIRECTORY) {
+                        Ok(path) => println!("Saved a screenshot to {}", path.display()),
+                        Err(e) => eprintln!("{}", e),
+                    }
                 }
+                self.record_frame();
+                self.present();
             }
+            MainLoopResult::Resize(width, height) => {
+                self.resize(width, height);
+            }
+        }
+
+        // Update the elapsed time, recordings can fix it to their frame rate
+        self.frame_count += 1;
+        self.elapsed_time = self
+            .recorder
+            .as_ref()
+            .and_then(FrameRecorder::frame_time)
+            .unwrap_or_else(|| self.inner_timer.elapsed());
+
+        result
+    }
 
-            // Update the elapsed time
-            elapsed_time = self.inner_timer.elapsed();
+    /// Saves what has been drawn in the current frame as a timestamped PNG in the directory,
+    /// with a sidecar file recording the shader files and uniform values of every program
+    pub fn screenshot<P: AsRef<Path>>(&self, directory: P) -> Result<PathBuf, String> {
+        match (self.window_manager.as_ref(), self.headless.as_ref()) {
+            (Some(window_manager), _) => window_manager.screenshot(directory),
+            (None, Some(headless)) => headless.screenshot(directory),
+            (None, None) => Err("Window not created, call `finalize_window` first".to_string()),
+        }
+    }
+
+    /// Takes a screenshot into `screenshots/` at the end of the current frame
+    pub fn request_screenshot(&self) {
+        self.screenshot_requested.set(true);
+    }
+
+    /// Sets the key that takes a screenshot into `screenshots/`, None removes it.
+    /// The key is seen even when the loop function handles its events, headless engines have no keys
+    pub fn set_screenshot_key(&mut self, key: Option<Keycode>) -> Result<(), String> {
+        self.screenshot_key = None;
+        let Some(key) = key else {
+            return Ok(());
+        };
+        let Some(sdl) = self.sdl.as_ref() else {
+            return Err("Headless engines have no keys, use `request_screenshot`".to_string());
+        };
+
+        let requested = self.screenshot_requested.clone();
+        let callback: Box<dyn FnMut(Event)> = Box::new(move |event| {
+            let pressed = matches!(
+                event,
+                Event::KeyDown {
+                    keycode: Some(pressed),
+                    repeat: false,
+                    ..
+                } if pressed == key
+            );
+            if pressed {
+                requested.set(true);
+            }
+        });
+
+        let event_subsystem = sdl.event()?;
+        let watch = event_subsystem.add_event_watch(callback);
+        self.screenshot_key = Some((watch, event_subsystem));
+        Ok(())
+    }
+
+    /// Starts saving the frames at the end of each frame, replacing the current recording.
+    /// With a fixed timestep the next frame already gets its frame time
+    pub fn start_recording(&mut self, recorder: FrameRecorder) -> Result<(), String> {
+        self.stop_recording()?;
+        if let Some(frame_time) = recorder.frame_time() {
+            self.elapsed_time = frame_time;
+        }
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    /// Stops the recording and finishes writing it, returns the number of frames saved
+    pub fn stop_recording(&mut self) -> Result<u64, String> {
+        let Some(mut recorder) = self.recorder.take() else {
+            return Ok(0);
+        };
+        recorder.finish()?;
+        Ok(recorder.frames_written())
+    }
+
+    pub fn recorder(&self) -> Option<&FrameRecorder> {
+        self.recorder.as_ref()
+    }
+
+    /// Saves the frame into the recording, a failed frame stops the recording
+    fn record_frame(&mut self) {
+        let Some((framebuffer, color_buffer, width, height)) = self.color_buffer() else {
+            return;
+        };
+        let Some(recorder) = self.recorder.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = recorder.record_frame(framebuffer, color_buffer, width, height) {
+            eprintln!("{}, stopping the recording", e);
+            let _ = self.stop_recording();
+        }
+    }
+
+    /// Framebuffer and color buffer the frame is drawn to, with its size
+    fn color_buffer(&self) -> Option<(GLuint, GLenum, i32, i32)> {
+        match (self.window_manager.as_ref(), self.headless.as_ref()) {
+            (Some(window_manager), _) => {
+                Some((0, gl::BACK, window_manager.width, window_manager.height))
+            }
+            (None, Some(headless)) => Some((
+                headless.framebuffer,
+                gl::COLOR_ATTACHMENT0,
+                headless.width,
+                headless.height,
+            )),
+            (None, None) => None,
         }
     }
 
@@ -95,6 +342,11 @@ impl ShaderEngine {
             panic!("Window not created, call `finalize_window` first");
         }
 
+        if let Some(headless) = self.headless.as_mut() {
+            headless.resize(width, height);
+            return;
+        }
+
         let window_manager = self.window_manager.as_mut().unwrap();
         window_manager.resize(width, height);
 
@@ -102,4 +354,13 @@ impl ShaderEngine {
             gl::Viewport(0, 0, width as GLsizei, height as GLsizei);
         }
     }
+
+    /// Shows the frame, headless engines only wait for it to finish
+    fn present(&mut self) {
+        match (self.window_manager.as_mut(), self.headless.as_mut()) {
+            (Some(window_manager), _) => window_manager.swap_window(),
+            (None, Some(headless)) => headless.finish_frame(),
+            (None, None) => {}
+        }
+    }
 }
@@ -0,0 +1,271 @@
+use crate::image_export::{save_png, ExportOptions};
+use crate::screenshot::read_color_buffer;
+use gl::types::{GLenum, GLuint};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Where the recorded frames go
+#[derive(Clone, Debug)]
+pub enum RecordTarget {
+    /// Numbered PNGs in a directory, like `frame_000042.png`
+    PngSequence(PathBuf),
+    /// A single uncompressed YUV4MPEG2 video, which ffmpeg and most players read directly
+    Y4m(PathBuf),
+}
+
+/// Saves the frames of the engine while it runs, started with `ShaderEngine::start_recording`
+pub struct FrameRecorder {
+    target: RecordTarget,
+    every: u64,
+    fps: u32,
+    fixed_timestep: bool,
+
+    frames_seen: u64,
+    frames_written: u64,
+    /// Opened on the first frame, the header needs the size
+    video: Option<Y4mWriter>,
+}
+
+impl FrameRecorder {
+    /// Records into numbered PNGs in the directory, which is created when missing
+    pub fn png_sequence<P: Into<PathBuf>>(directory: P) -> FrameRecorder {
+        Self::new(RecordTarget::PngSequence(directory.into()))
+    }
+
+    /// Records into a Y4M video file, every frame has to keep the size of the first one
+    pub fn y4m<P: Into<PathBuf>>(path: P) -> FrameRecorder {
+        Self::new(RecordTarget::Y4m(path.into()))
+    }
+
+    fn new(target: RecordTarget) -> FrameRecorder {
+        FrameRecorder {
+            target,
+            every: 1,
+            fps: 30,
+            fixed_timestep: false,
+
+            frames_seen: 0,
+            frames_written: 0,
+            video: None,
+        }
+    }
+
+    /// Only saves every nth frame, starting with the first one.
+    /// With a fixed timestep the frames in between share the `1 / fps` of the saved one
+    pub fn every(mut self, every: u64) -> FrameRecorder {
+        assert!(every > 0, "Frames are recorded every 1 or more frames");
+        self.every = every;
+        self
+    }
+
+    /// Frame rate written in the Y4M header, and the timestep when it is fixed
+    pub fn fps(mut self, fps: u32) -> FrameRecorder {
+        assert!(fps > 0, "The frame rate can't be 0");
+        self.fps = fps;
+        self
+    }
+
+    /// Makes the loop get exactly `1 / (fps * every)` as the elapsed time of every frame while recording,
+    /// so the saved frames are `1 / fps` apart like the video plays them, however long the frames really take
+    pub fn fixed_timestep(mut self) -> FrameRecorder {
+        self.fixed_timestep = true;
+        self
+    }
+
+    pub fn target(&self) -> &RecordTarget {
+        &self.target
+    }
+
+    /// Number of frames saved so far
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written
+    }
+
+    /// Elapsed time the loop gets for each frame, None when the real time is used
+    pub(crate) fn frame_time(&self) -> Option<f64> {
+        self.fixed_timestep
+            .then(|| 1.0 / (self.fps as f64 * self.every as f64))
+    }
+
+    /// Called at the end of every frame, before it is shown. Saves the frame when it is one of the nth frames
+    pub(crate) fn record_frame(
+        &mut self,
+        framebuffer: GLuint,
+        color_buffer: GLenum,
+        width: i32,
+        height: i32,
+    ) -> Result<(), String> {
+        let frame = self.frames_seen;
+        self.frames_seen += 1;
+        if !frame.is_multiple_of(self.every) {
+            return Ok(());
+        }
+
+        let pixels = read_color_buffer(framebuffer, color_buffer, width, height);
+        match &self.target {
+            RecordTarget::PngSequence(directory) => {
+                if self.frames_written == 0 {
+                    fs::create_dir_all(directory)
+                        .map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+                }
+
+                let path = directory.join(format!("frame_{:06}.png", self.frames_written));
+                save_png(
+                    path,
+                    width as u32,
+                    height as u32,
+                    3,
+                    &pixels,
+                    &ExportOptions::new(),
+                )?;
+            }
+            RecordTarget::Y4m(path) => {
+                let video = match self.video.as_mut() {
+                    Some(video) => video,
+                    None => self
+                        .video
+                        .insert(Y4mWriter::create(path, width, height, self.fps)?),
+                };
+                video.write_frame(width, height, &pixels)?;
+            }
+        }
+
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    /// Flushes the video, the PNGs are already on disk
+    pub(crate) fn finish(&mut self) -> Result<(), String> {
+        match self.video.as_mut() {
+            Some(video) => video.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes YUV4MPEG2 with full resolution chroma (`C444`), so no pixel formats need converting in between
+struct Y4mWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    width: i32,
+    height: i32,
+    /// Y, Cb and Cr planes of the current frame
+    planes: Vec<u8>,
+}
+
+impl Y4mWriter {
+    fn create(path: &Path, width: i32, height: i32, fps: u32) -> Result<Y4mWriter, String> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+
+        let mut writer = Y4mWriter {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            width,
+            height,
+            planes: vec![0; width as usize * height as usize * 3],
+        };
+
+        let header = format!("YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n", width, height, fps);
+        writer.write(header.as_bytes())?;
+        Ok(writer)
+    }
+
+    /// Converts the bottom up RGB pixels to limited range BT.601, which is what players assume for Y4M
+    fn write_frame(&mut self, width: i32, height: i32, pixels: &[f32]) -> Result<(), String> {
+        if width != self.width || height != self.height {
+            return Err(format!(
+                "Y4M frames must keep the size {}x{}, got {}x{}",
+                self.width, self.height, width, height
+            ));
+        }
+
+        let plane_size = width as usize * height as usize;
+        let (luma, chroma) = self.planes.split_at_mut(plane_size);
+        let (blue_difference, red_difference) = chroma.split_at_mut(plane_size);
+
+        let rows = pixels.chunks_exact(width as usize * 3).rev();
+        for (index, pixel) in rows.flat_map(|row| row.chunks_exact(3)).enumerate() {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|value| value.clamp(0.0, 1.0));
+
+            let y = 0.299 * r + 0.587 * g + 0.114 * b;
+            luma[index] = (16.0 + 219.0 * y).round() as u8;
+            blue_difference[index] = (128.0 + 224.0 * (b - y) / 1.772).round() as u8;
+            red_difference[index] = (128.0 + 224.0 * (r - y) / 1.402).round() as u8;
+        }
+
+        self.write(b"FRAME\n")?;
+        let planes = std::mem::take(&mut self.planes);
+        let result = self.write(&planes);
+        self.planes = planes;
+        result
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.file
+            .write_all(bytes)
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.file
+            .flush()
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_timestep_spreads_the_frame_rate_over_the_skipped_frames() {
+        let recorder = FrameRecorder::y4m("unused.y4m").fps(30);
+        assert_eq!(recorder.frame_time(), None);
+        assert_eq!(recorder.fixed_timestep().frame_time(), Some(1.0 / 30.0));
+
+        let recorder = FrameRecorder::y4m("unused.y4m")
+            .fps(30)
+            .every(2)
+            .fixed_timestep();
+        assert_eq!(recorder.frame_time(), Some(1.0 / 60.0));
+    }
+
+    #[test]
+    fn y4m_frames_are_limited_range_bt601_from_the_top() {
+        let path =
+            std::env::temp_dir().join(format!("shader_engine_test_{}.y4m", std::process::id()));
+        let mut video = Y4mWriter::create(&path, 2, 2, 24).unwrap();
+        // Bottom row first: black, white, then the top row: red, out of range blue
+        let pixels = [
+            0.0, 0.0, 0.0, 1.0, 1.0, 1.0, //
+            1.0, 0.0, 0.0, 0.0, 0.0, 2.0,
+        ];
+        video.write_frame(2, 2, &pixels).unwrap();
+        assert!(video.write_frame(1, 2, &pixels[..6]).is_err());
+        video.flush().unwrap();
+        drop(video);
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let header = b"YUV4MPEG2 W2 H2 F24:1 Ip A1:1 C444\nFRAME\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let planes = &bytes[header.len()..];
+        assert_eq!(planes.len(), 12);
+
+        let (luma, chroma) = planes.split_at(4);
+        let (blue_difference, red_difference) = chroma.split_at(4);
+        assert_eq!(luma, [81, 41, 16, 235]);
+        assert_eq!(blue_difference, [90, 240, 128, 128]);
+        assert_eq!(red_difference, [240, 110, 128, 128]);
+    }
+}
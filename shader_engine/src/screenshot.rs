@@ -0,0 +1,137 @@
+use crate::image_export::{save_png, ExportOptions};
+use crate::shader::record::describe_programs;
+use gl::types::{GLenum, GLsizei, GLuint};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the screenshot key saves the captures, relative to the working directory
+pub const SCREENSHOT_DIRECTORY: &str = "screenshots";
+
+/// Reads a color buffer of the framebuffer and saves it as a timestamped PNG in the directory.
+/// A `.txt` file with the same name records the files, defines and uniform values of every live program,
+/// so the shot can be reproduced. Returns the path of the PNG
+pub(crate) fn capture(
+    framebuffer: GLuint,
+    color_buffer: GLenum,
+    width: i32,
+    height: i32,
+    directory: &Path,
+) -> Result<PathBuf, String> {
+    let pixels = read_color_buffer(framebuffer, color_buffer, width, height);
+
+    fs::create_dir_all(directory)
+        .map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+
+    let path = directory.join(format!("{}.png", timestamp()));
+    save_png(
+        &path,
+        width as u32,
+        height as u32,
+        3,
+        &pixels,
+        &ExportOptions::new(),
+    )?;
+
+    let sidecar = path.with_extension("txt");
+    fs::write(&sidecar, describe_programs())
+        .map_err(|e| format!("Failed to save {}: {}", sidecar.display(), e))?;
+
+    Ok(path)
+}
+
+/// Reads a color buffer of the framebuffer as RGB floats, with the rows from the bottom like GL stores them
+pub(crate) fn read_color_buffer(
+    framebuffer: GLuint,
+    color_buffer: GLenum,
+    width: i32,
+    height: i32,
+) -> Vec<f32> {
+    let mut pixels = vec![0.0f32; width as usize * height as usize * 3];
+    unsafe {
+        let mut previous = 0;
+        let mut alignment = 0;
+        gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous);
+        gl::GetIntegerv(gl::PACK_ALIGNMENT, &mut alignment);
+
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+        gl::ReadBuffer(color_buffer);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as GLsizei,
+            height as GLsizei,
+            gl::RGB,
+            gl::FLOAT,
+            pixels.as_mut_ptr() as *mut _,
+        );
+
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous as GLuint);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, alignment);
+    }
+    pixels
+}
+
+/// Current UTC time like `2025-01-31_18-04-59-123`, sorts the same way as the time
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs() as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}-{:03}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
+
+/// Date of a day counted from 1970-01-01, Howard Hinnant's `civil_from_days`
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_become_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(19723), (2024, 1, 1));
+    }
+
+    #[test]
+    fn leap_years_follow_the_gregorian_rules() {
+        // 2000 is divisible by 400, 1900 and 2100 only by 100
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(47540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47541), (2100, 3, 1));
+        assert_eq!(civil_from_days(-25508), (1900, 3, 1));
+    }
+}
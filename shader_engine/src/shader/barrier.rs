@@ -0,0 +1,147 @@
+use crate::shader::introspection::{glsl_type_name, ProgramInterface};
+use gl::types::GLbitfield;
+use std::cell::Cell;
+use std::ops::{BitOr, BitOrAssign};
+
+thread_local! {
+    /// Barrier bits of the dispatches made inside `MemoryBarrier::batch`, None outside of a batch
+    static PENDING: Cell<Option<GLbitfield>> = const { Cell::new(None) };
+}
+
+/// The `glMemoryBarrier` bits a compute dispatch issues, so the next commands see what the dispatch wrote.
+/// Combined with `|`, like `MemoryBarrier::IMAGE_ACCESS | MemoryBarrier::TEXTURE_FETCH`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryBarrier(GLbitfield);
+
+impl MemoryBarrier {
+    pub const NONE: MemoryBarrier = MemoryBarrier(0);
+    pub const ALL: MemoryBarrier = MemoryBarrier(gl::ALL_BARRIER_BITS);
+
+    /// Images read with `imageLoad` or written with `imageStore`
+    pub const IMAGE_ACCESS: MemoryBarrier = MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    /// Images sampled as textures, like the screen image in the quad shader
+    pub const TEXTURE_FETCH: MemoryBarrier = MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT);
+    /// Textures read back or updated from the CPU
+    pub const TEXTURE_UPDATE: MemoryBarrier = MemoryBarrier(gl::TEXTURE_UPDATE_BARRIER_BIT);
+    /// Storage buffers accessed by shaders
+    pub const STORAGE_BUFFER: MemoryBarrier = MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+    /// Buffers read back, copied or updated from the CPU, like `StorageBuffer::read_back`
+    pub const BUFFER_UPDATE: MemoryBarrier = MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+    /// Buffers used as indirect commands, like the ones of `dispatch_indirect`
+    pub const COMMAND: MemoryBarrier = MemoryBarrier(gl::COMMAND_BARRIER_BIT);
+
+    pub fn bits(self) -> GLbitfield {
+        self.0
+    }
+
+    pub fn contains(self, other: MemoryBarrier) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Guesses the barrier from what the program can write. Images can be read back as images or textures,
+    /// storage buffers by shaders, the CPU or as indirect commands. Falls back to ALL when nothing is found
+    pub(crate) fn infer(interface: &ProgramInterface) -> MemoryBarrier {
+        let mut barrier = MemoryBarrier::NONE;
+
+        // Integer images are prefixed, like `iimage2D`
+        let has_images = interface
+            .uniforms
+            .iter()
+            .any(|uniform| glsl_type_name(uniform.gl_type).is_some_and(|ty| ty.contains("image")));
+        if has_images {
+            barrier |= MemoryBarrier::IMAGE_ACCESS
+                | MemoryBarrier::TEXTURE_FETCH
+                | MemoryBarrier::TEXTURE_UPDATE;
+        }
+
+        if !interface.storage_blocks.is_empty() {
+            barrier |= MemoryBarrier::STORAGE_BUFFER
+                | MemoryBarrier::BUFFER_UPDATE
+                | MemoryBarrier::COMMAND;
+        }
+
+        if barrier == MemoryBarrier::NONE {
+            MemoryBarrier::ALL
+        } else {
+            barrier
+        }
+    }
+
+    /// Issues the barrier, or adds it to the current batch
+    pub fn issue(self) {
+        let batched = PENDING.with(|pending| match pending.get() {
+            Some(bits) => {
+                pending.set(Some(bits | self.0));
+                true
+            }
+            None => false,
+        });
+
+        if !batched && self.0 != 0 {
+            unsafe {
+                gl::MemoryBarrier(self.0);
+            }
+        }
+    }
+
+    /// Runs the dispatches without barriers between them, then issues a single barrier with every bit they needed.
+    /// Only for dispatches that don't read what the others in the batch write
+    pub fn batch<R>(dispatches: impl FnOnce() -> R) -> R {
+        // Nested batches join the outer one
+        if PENDING.with(|pending| pending.get().is_some()) {
+            return dispatches();
+        }
+
+        PENDING.with(|pending| pending.set(Some(0)));
+        let batch = BatchGuard;
+        let result = dispatches();
+        drop(batch);
+        result
+    }
+}
+
+/// Ends the batch and issues its barrier, even when a dispatch panics so the later barriers aren't swallowed
+struct BatchGuard;
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        let bits = PENDING.with(|pending| pending.take()).unwrap_or(0);
+        MemoryBarrier(bits).issue();
+    }
+}
+
+impl BitOr for MemoryBarrier {
+    type Output = MemoryBarrier;
+
+    fn bitor(self, rhs: MemoryBarrier) -> MemoryBarrier {
+        MemoryBarrier(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for MemoryBarrier {
+    fn bitor_assign(&mut self, rhs: MemoryBarrier) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+
+    #[test]
+    fn batch_ends_when_a_dispatch_panics() {
+        let result = panic::catch_unwind(|| MemoryBarrier::batch(|| panic!("dispatch failed")));
+        assert!(result.is_err());
+        assert_eq!(PENDING.with(|pending| pending.get()), None);
+    }
+
+    #[test]
+    fn nested_batches_join_the_outer_one() {
+        MemoryBarrier::batch(|| {
+            MemoryBarrier::batch(|| {});
+            assert_eq!(PENDING.with(|pending| pending.get()), Some(0));
+        });
+        assert_eq!(PENDING.with(|pending| pending.get()), None);
+    }
+}
@@ -1,12 +1,20 @@
+use std::ops::Range;
+
 #[derive(Debug)]
 pub struct Bindable<T> {
     value: T,
     dirty: bool,
+    /// Only used by arrays, elements changed one by one since the last upload
+    dirty_elements: Vec<bool>,
 }
 
 impl<T> Bindable<T> {
     pub fn new(value: T) -> Bindable<T> {
-        Bindable { value, dirty: true }
+        Bindable {
+            value,
+            dirty: true,
+            dirty_elements: Vec::new(),
+        }
     }
 
     pub fn set(&mut self, value: T) {
@@ -19,11 +27,46 @@ impl<T> Bindable<T> {
     }
 
     pub fn is_dirty(&self) -> bool {
-        self.dirty
+        self.dirty || self.dirty_elements.contains(&true)
     }
 
     pub fn clear_dirty(&mut self) {
         self.dirty = false;
+        self.dirty_elements.fill(false);
+    }
+
+    /// Uploads the value again on the next use, even though it didn't change
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+}
+
+impl<T> Bindable<Vec<T>> {
+    /// Changes a single element, so only that element gets uploaded
+    pub fn set_element(&mut self, index: usize, value: T) {
+        self.value[index] = value;
+        if self.dirty_elements.len() < self.value.len() {
+            self.dirty_elements.resize(self.value.len(), false);
+        }
+        self.dirty_elements[index] = true;
+    }
+
+    pub fn get_element(&self, index: usize) -> &T {
+        &self.value[index]
+    }
+
+    /// Ranges of neighbouring elements that need to be uploaded, the whole array if it was set at once
+    pub(crate) fn dirty_runs(&self) -> Vec<Range<usize>> {
+        let is_dirty = |index: &usize| self.dirty || self.dirty_elements.get(*index) == Some(&true);
+
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for index in (0..self.value.len()).filter(is_dirty) {
+            match runs.last_mut() {
+                Some(run) if run.end == index => run.end += 1,
+                _ => runs.push(index..index + 1),
+            }
+        }
+        runs
     }
 }
 
@@ -0,0 +1,158 @@
+use crate::utils::colorized_text::{ColoredText, Colorize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Guesses the severity of a message nobody could parse
+    pub(crate) fn guess(message: &str) -> Severity {
+        let lowercase = message.to_lowercase();
+        if lowercase.contains("error") {
+            Severity::Error
+        } else if lowercase.contains("warning") {
+            Severity::Warning
+        } else {
+            Severity::Info
+        }
+    }
+
+    pub(crate) fn colorize<T: Colorize>(&self, text: T) -> ColoredText {
+        match self {
+            Severity::Info => text.cyan(),
+            Severity::Warning => text.yellow(),
+            Severity::Error => text.red(),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single message about a shader, pointing to the original file when the position is known
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+    /// Stage of the shader the message belongs to, like "vertex" or "compute"
+    pub stage: Option<String>,
+}
+
+impl Diagnostic {
+    /// A message without a position, like the lines of a driver log no parser understood
+    pub fn raw(message: &str) -> Diagnostic {
+        Diagnostic {
+            file: None,
+            line: None,
+            column: None,
+            severity: Severity::guess(message),
+            message: message.to_string(),
+            stage: None,
+        }
+    }
+
+    /// An error from the engine itself, like a preprocessor error
+    pub(crate) fn error(file: Option<&str>, line: Option<usize>, message: String) -> Diagnostic {
+        Diagnostic {
+            file: file.map(str::to_string),
+            line,
+            column: None,
+            severity: Severity::Error,
+            message,
+            stage: None,
+        }
+    }
+
+    /// Position formatted as `line:column`, or only `line` when there is no column
+    pub(crate) fn position(&self) -> Option<String> {
+        let line = self.line?;
+        match self.column {
+            Some(column) => Some(format!("{}:{}", line, column)),
+            None => Some(line.to_string()),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+            if let Some(position) = self.position() {
+                write!(f, "{}:", position)?;
+            }
+            write!(f, " ")?;
+        } else if let Some(stage) = &self.stage {
+            write!(f, "{} shader: ", stage)?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Why a shader or a program couldn't be built, with every diagnostic that was collected
+#[derive(Clone, Debug)]
+pub enum ShaderError {
+    /// A shader file couldn't be found or read
+    Io(Vec<Diagnostic>),
+    /// The preprocessor rejected the source, like an unterminated `#if` or an include cycle
+    Preprocess(Vec<Diagnostic>),
+    /// The driver failed to compile a shader stage
+    Compile(Vec<Diagnostic>),
+    /// The driver failed to link the program
+    Link(Vec<Diagnostic>),
+}
+
+impl ShaderError {
+    pub(crate) fn io(file: Option<&str>, line: Option<usize>, message: String) -> ShaderError {
+        ShaderError::Io(vec![Diagnostic::error(file, line, message)])
+    }
+
+    pub(crate) fn preprocess(
+        file: Option<&str>,
+        line: Option<usize>,
+        message: String,
+    ) -> ShaderError {
+        ShaderError::Preprocess(vec![Diagnostic::error(file, line, message)])
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            ShaderError::Io(diagnostics)
+            | ShaderError::Preprocess(diagnostics)
+            | ShaderError::Compile(diagnostics)
+            | ShaderError::Link(diagnostics) => diagnostics,
+        }
+    }
+}
+
+impl Display for ShaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShaderError::Io(_) => write!(f, "Failed to read the shader files")?,
+            ShaderError::Preprocess(_) => write!(f, "Failed to preprocess the shader")?,
+            ShaderError::Compile(_) => write!(f, "Failed to compile the shader")?,
+            ShaderError::Link(_) => write!(f, "Failed to link the program")?,
+        }
+
+        for diagnostic in self.diagnostics() {
+            write!(f, "\n{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ShaderError {}
@@ -1,105 +1,224 @@
+use crate::shader::diagnostic::{Diagnostic, Severity, ShaderError};
+use crate::shader::log_parser::parse_log;
+use crate::shader::options::ShaderOptions;
 use crate::shader::preprocessor::ProcessData;
-use crate::utils::colorized_text::Colorize;
+use crate::shader::shader_gen::stage_name;
+use crate::utils::colorized_text::{ColoredText, Colorize};
 use crate::utils::html_logger::HTMLLogger;
-use gl::types::{GLchar, GLint, GLuint};
-use regex::Regex;
-use std::collections::HashMap;
-use std::ffi::CString;
-use std::ptr;
+use crate::utils::nested_console_logger::NestedConsoleLogger;
+use gl::types::{GLchar, GLenum, GLint, GLsizei, GLuint};
 
-const SHADER_LINE_FORMAT: &str = r"0\((\d+)\) : error .{5}: (.+)";
+/// Lines shown before the line a diagnostic points to
+const SNIPPET_CONTEXT_LINES: usize = 2;
 
 /// Checks if a shader has compiled successfully or not
-/// Depending on if it hasn't compiled successfully,
-/// it will print out a well-formatted error message,
-/// which includes the line and file the error occurred on (required for #include)
+/// The info log is read even when it has, so the warnings are not lost.
+/// Every message is printed out well-formatted,
+/// which includes the line and file it occurred on (required for #include)
 pub fn check_shader(
     logger: &mut HTMLLogger,
     shader: GLuint,
     data: &ProcessData,
-) -> Result<(), String> {
+    options: &ShaderOptions,
+) -> Result<Vec<Diagnostic>, ShaderError> {
     // Success flag determines if the shader compiled successfully
     let mut success: GLint = 0;
     unsafe {
         gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
     }
 
-    // If the shader failed to compile, success will be 0
-    if success == 0 {
-        // Get the length of the error message
-        let mut len: GLint = 0;
-        unsafe {
-            gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-        }
-
-        // Create a buffer out of the length of the error message.
-        // gl functions require null terminated strings, so we create a CString from the buffer
-        let mut buffer: Vec<u8> = Vec::with_capacity(len as usize + 1);
-        buffer.extend([b' '].iter().cycle().take(len as usize));
-        let error_message = unsafe { CString::from_vec_unchecked(buffer) };
-
-        unsafe {
-            gl::GetShaderInfoLog(
-                shader,
-                len,
-                ptr::null_mut(),
-                error_message.as_ptr() as *mut GLchar,
-            );
-        }
+    let mut shader_type: GLint = 0;
+    unsafe {
+        gl::GetShaderiv(shader, gl::SHADER_TYPE, &mut shader_type);
+    }
 
-        // Format the error message and print it
-        let readable_error = error_message.to_string_lossy().into_owned();
-        shader_error_handler(logger, readable_error.as_str(), data);
+    let info_log = read_info_log(shader, gl::GetShaderiv, gl::GetShaderInfoLog);
+    let mut diagnostics = parse_log(info_log.as_str(), &options.log_parsers, data);
+    for diagnostic in diagnostics.iter_mut() {
+        diagnostic.stage = Some(stage_name(shader_type as GLuint).to_string());
+    }
+    log_diagnostics(logger, "Compilation", &diagnostics, &[data]);
 
-        return Err("Error compiling shader".to_string());
+    // If the shader failed to compile, success will be 0
+    if success == 0 || (options.warnings_as_errors && has_warnings(&diagnostics)) {
+        return Err(ShaderError::Compile(diagnostics));
     }
 
-    Ok(())
+    Ok(diagnostics)
 }
 
-/// Handles the error message from a shader
-/// and formats it into an easy-to-understand, readable message
-fn shader_error_handler(logger: &mut HTMLLogger, error_message: &str, data: &ProcessData) {
-    let line_number_regex = Regex::new(SHADER_LINE_FORMAT).unwrap();
-
-    logger.open_scope("Compilation Errors".red());
+/// Reads the whole info log of a shader or a program, empty when the driver had nothing to say
+pub(crate) fn read_info_log(
+    object: GLuint,
+    get_parameter: unsafe fn(GLuint, GLenum, *mut GLint),
+    get_info_log: unsafe fn(GLuint, GLsizei, *mut GLsizei, *mut GLchar),
+) -> String {
+    // Get the length of the message, including the null terminator
+    let mut len: GLint = 0;
+    unsafe {
+        get_parameter(object, gl::INFO_LOG_LENGTH, &mut len);
+    }
+    if len <= 0 {
+        return String::new();
+    }
 
-    // Generate a map for each source file and its errors
-    let mut errors_by_source: HashMap<String, Vec<(usize, String)>> = HashMap::new();
-    for source in data.included_files.iter() {
-        errors_by_source.insert(source.clone(), Vec::new());
+    let mut buffer: Vec<u8> = vec![0; len as usize];
+    let mut written: GLsizei = 0;
+    unsafe {
+        get_info_log(
+            object,
+            len,
+            &mut written,
+            buffer.as_mut_ptr() as *mut GLchar,
+        );
     }
+    buffer.truncate(written.max(0) as usize);
 
-    // Handle each line of the error
-    for line in error_message.lines() {
-        // Skip the null terminator
-        if line == "\0" {
-            continue;
-        }
+    String::from_utf8_lossy(&buffer).into_owned()
+}
 
-        let capture = line_number_regex.captures(line).unwrap();
-        let line_number = capture.get(1).unwrap().as_str().parse::<usize>().unwrap();
-        let error = capture.get(2).unwrap().as_str().to_string();
+pub(crate) fn has_warnings(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Warning)
+}
 
-        let (source, _) = data.line_to_source[line_number - 1].clone();
-        let errors = errors_by_source.get_mut(&source).unwrap();
-        errors.push((line_number, error));
+/// Formats the diagnostics of a stage into an easy-to-understand, readable message,
+/// grouped by the file they point to and shown with the lines around them.
+/// The message goes both to the HTML log and to the console
+pub(crate) fn log_diagnostics(
+    logger: &mut HTMLLogger,
+    stage: &str,
+    diagnostics: &[Diagnostic],
+    data: &[&ProcessData],
+) {
+    let Some(severity) = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.severity)
+        .max()
+    else {
+        return;
+    };
+
+    let title = match severity {
+        Severity::Error => format!("{} Errors", stage).red(),
+        Severity::Warning => format!("{} Warnings", stage).yellow(),
+        Severity::Info => format!("{} Messages", stage).cyan(),
+    };
+    let mut console = NestedConsoleLogger::default();
+    logger.open_scope(&title);
+    console.open_scope(&title);
+
+    // Files in the order they were included, each one only once
+    let mut files: Vec<&String> = Vec::new();
+    for file in data.iter().flat_map(|data| data.included_files.iter()) {
+        if !files.contains(&file) {
+            files.push(file);
+        }
     }
 
-    // Log the errors
-    for source in data.included_files.iter() {
-        let errors = errors_by_source.get(source).unwrap();
-        if errors.is_empty() {
+    // Log the diagnostics of each source file
+    for source in files {
+        let diagnostics = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.file.as_ref() == Some(source))
+            .collect::<Vec<_>>();
+        if diagnostics.is_empty() {
             continue;
         }
 
+        let contents = data.iter().find_map(|data| data.sources.get(source));
         logger.open_scope(source.yellow());
-        for (line_number, error) in errors.iter() {
-            let actual_line_number = data.line_to_source[*line_number - 1].1;
-            logger.log(format!("{}", actual_line_number).red() + ": ".cyan() + error.white());
+        console.open_scope(source.yellow());
+        for diagnostic in diagnostics {
+            let position = diagnostic.position().unwrap_or_default();
+            let header = diagnostic.severity.colorize(position.as_str())
+                + ": ".cyan()
+                + diagnostic.message.white();
+            logger.log(&header);
+            console.log(&header);
+
+            let snippet = contents
+                .map(|contents| source_snippet(diagnostic, contents))
+                .unwrap_or_default();
+            for line in snippet {
+                logger.log(&line);
+                console.log(&line);
+            }
         }
         logger.close_scope();
+        console.close_scope("");
+    }
+
+    // Messages without a known file are logged as they are, after the stage they belong to
+    for diagnostic in diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.file.is_none())
+    {
+        let mut message = diagnostic.severity.colorize(diagnostic.message.as_str());
+        if let Some(stage) = &diagnostic.stage {
+            message = format!("{} shader: ", stage).magenta() + message;
+        }
+        logger.log(&message);
+        console.log(&message);
     }
 
     logger.close_scope();
+    console.close_scope("");
+}
+
+/// The line a diagnostic points to with a few lines before it, like:
+/// ```text
+///  9 | vec3 color = vec3(0.0);
+/// 10 | float t = 1.0;
+/// 11 | color += undefined_value;
+///    |          ^^^^^^^^^^^^^^^
+/// ```
+/// The marker is only added when the driver gave a column
+fn source_snippet(diagnostic: &Diagnostic, contents: &str) -> Vec<ColoredText> {
+    let Some(line_number) = diagnostic.line else {
+        return Vec::new();
+    };
+    let lines = contents.lines().collect::<Vec<_>>();
+    let Some(line) = line_number
+        .checked_sub(1)
+        .and_then(|index| lines.get(index))
+    else {
+        return Vec::new();
+    };
+
+    let first_line = line_number.saturating_sub(SNIPPET_CONTEXT_LINES).max(1);
+    let gutter_width = line_number.to_string().len();
+    let mut snippet = (first_line..=line_number)
+        .map(|number| {
+            format!("{:>width$} | ", number, width = gutter_width).cyan()
+                + lines[number - 1].white()
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(column) = diagnostic.column {
+        // Drivers count the columns from 1, tabs are kept so the marker lines up with the code
+        let start = column.max(1) - 1;
+        let padding = line
+            .chars()
+            .take(start)
+            .map(|char| if char == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+
+        // The marker covers the whole identifier or number at the column
+        let length = line
+            .chars()
+            .skip(start)
+            .take_while(|char| char.is_alphanumeric() || *char == '_')
+            .count()
+            .max(1);
+
+        snippet.push(
+            format!("{} | ", " ".repeat(gutter_width)).cyan()
+                + padding.white()
+                + diagnostic.severity.colorize("^".repeat(length)),
+        );
+    }
+
+    snippet
 }
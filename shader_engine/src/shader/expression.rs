@@ -0,0 +1,541 @@
+use crate::shader::preprocessor::Macro;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Identifier(String),
+    Punct(&'static str),
+}
+
+/// Every operator a `#if` expression can contain, longest ones first so that they match greedily
+const PUNCTUATIONS: [&str; 25] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "(", ")", ",", "?", ":", "+", "-", "*", "/",
+    "%", "<", ">", "&", "^", "|", "!", "~",
+];
+
+#[derive(Debug)]
+enum Expression {
+    Number(i64),
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    Ternary(Box<Expression>, Box<Expression>, Box<Expression>),
+}
+
+/// Evaluates the expression of an `#if` or `#elif` directive.
+/// Macros are expanded with the given defines, unknown identifiers evaluate to 0
+pub(crate) fn evaluate(expression: &str, defines: &HashMap<String, Macro>) -> Result<i64, String> {
+    let tokens = tokenize(expression)?;
+    let tokens = replace_defined(tokens, defines)?;
+    let tokens = expand(tokens, defines, &mut Vec::new())?
+        .into_iter()
+        .map(|token| match token {
+            Token::Identifier(_) => Token::Number(0),
+            token => token,
+        })
+        .collect::<Vec<_>>();
+
+    if tokens.is_empty() {
+        return Err("#if with no expression".to_string());
+    }
+
+    parse(tokens)
+}
+
+/// Evaluates a constant expression outside of the directives, like the size of an array.
+/// None when it names something that isn't a macro, like a `const int`, or isn't a preprocessor expression,
+/// only the compiler knows those
+pub(crate) fn evaluate_constant(expression: &str, defines: &HashMap<String, Macro>) -> Option<i64> {
+    let tokens = expand(tokenize(expression).ok()?, defines, &mut Vec::new()).ok()?;
+    if tokens.is_empty()
+        || tokens
+            .iter()
+            .any(|token| matches!(token, Token::Identifier(_)))
+    {
+        return None;
+    }
+
+    parse(tokens).ok()
+}
+
+fn parse(tokens: Vec<Token>) -> Result<i64, String> {
+    let mut parser = Parser { tokens, index: 0 };
+    let expression = parser.ternary()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("Unexpected token {:?} in expression", token));
+    }
+
+    expression.evaluate()
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let char = chars[index];
+        if char.is_whitespace() {
+            index += 1;
+            continue;
+        }
+
+        // Identifiers and keywords
+        if char.is_ascii_alphabetic() || char == '_' {
+            let start = index;
+            while index < chars.len()
+                && (chars[index].is_ascii_alphanumeric() || chars[index] == '_')
+            {
+                index += 1;
+            }
+            tokens.push(Token::Identifier(chars[start..index].iter().collect()));
+            continue;
+        }
+
+        // Integer literals, with the optional unsigned suffix
+        if char.is_ascii_digit() {
+            let start = index;
+            while index < chars.len()
+                && (chars[index].is_ascii_alphanumeric() || chars[index] == '.')
+            {
+                index += 1;
+            }
+            let literal = chars[start..index].iter().collect::<String>();
+            tokens.push(Token::Number(parse_integer(&literal)?));
+            continue;
+        }
+
+        let rest = chars[index..].iter().take(2).collect::<String>();
+        match PUNCTUATIONS.iter().find(|punct| rest.starts_with(**punct)) {
+            Some(punct) => {
+                tokens.push(Token::Punct(punct));
+                index += punct.len();
+            }
+            None => return Err(format!("Unexpected character '{}' in expression", char)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_integer(literal: &str) -> Result<i64, String> {
+    let digits = literal.trim_end_matches(['u', 'U']);
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse::<i64>()
+    };
+
+    parsed.map_err(|_| format!("Invalid integer literal \"{}\" in expression", literal))
+}
+
+/// Replaces `defined NAME` and `defined(NAME)` with 1 or 0, this has to happen before any expansion
+fn replace_defined(
+    tokens: Vec<Token>,
+    defines: &HashMap<String, Macro>,
+) -> Result<Vec<Token>, String> {
+    let mut result = Vec::new();
+    let mut iter = tokens.into_iter();
+
+    while let Some(token) = iter.next() {
+        if token != Token::Identifier("defined".to_string()) {
+            result.push(token);
+            continue;
+        }
+
+        let name = match iter.next() {
+            Some(Token::Identifier(name)) => name,
+            Some(Token::Punct("(")) => {
+                let name = match iter.next() {
+                    Some(Token::Identifier(name)) => name,
+                    _ => return Err("Expected a macro name after \"defined(\"".to_string()),
+                };
+                if iter.next() != Some(Token::Punct(")")) {
+                    return Err("Missing ')' after \"defined\"".to_string());
+                }
+                name
+            }
+            _ => return Err("Expected a macro name after \"defined\"".to_string()),
+        };
+
+        let value = defines.contains_key(&name) as i64;
+        result.push(Token::Number(value));
+    }
+
+    Ok(result)
+}
+
+/// Expands every macro in the token list, identifiers that aren't macros are left as they are.
+/// Like in C, a macro isn't expanded again inside its own expansion, so `#define FOO FOO` leaves `FOO`
+fn expand(
+    tokens: Vec<Token>,
+    defines: &HashMap<String, Macro>,
+    expanding: &mut Vec<String>,
+) -> Result<Vec<Token>, String> {
+    let mut result = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        let name = match &tokens[index] {
+            Token::Identifier(name) => name,
+            token => {
+                result.push(token.clone());
+                index += 1;
+                continue;
+            }
+        };
+        index += 1;
+
+        let Some(definition) = defines.get(name).filter(|_| !expanding.contains(name)) else {
+            result.push(Token::Identifier(name.clone()));
+            continue;
+        };
+
+        let body = tokenize(&definition.body)?;
+        let Some(params) = &definition.params else {
+            result.extend(expand_body(name, body, defines, expanding)?);
+            continue;
+        };
+
+        // Function like macros without arguments are not invocations
+        if tokens.get(index) != Some(&Token::Punct("(")) {
+            result.push(Token::Identifier(name.clone()));
+            continue;
+        }
+
+        let (arguments, next_index) = collect_arguments(&tokens, index + 1, name)?;
+        index = next_index;
+
+        if arguments.len() != params.len() && !(params.is_empty() && arguments == [vec![]]) {
+            return Err(format!(
+                "Macro \"{}\" expects {} arguments, got {}",
+                name,
+                params.len(),
+                arguments.len()
+            ));
+        }
+
+        // The arguments are expanded on their own first, where the macro can still be used
+        let arguments = arguments
+            .into_iter()
+            .map(|argument| expand(argument, defines, expanding))
+            .collect::<Result<Vec<_>, _>>()?;
+        let substituted = body
+            .into_iter()
+            .flat_map(|token| match &token {
+                Token::Identifier(ident) => match params.iter().position(|p| p == ident) {
+                    Some(position) => arguments[position].clone(),
+                    None => vec![token],
+                },
+                _ => vec![token],
+            })
+            .collect::<Vec<_>>();
+        result.extend(expand_body(name, substituted, defines, expanding)?);
+    }
+
+    Ok(result)
+}
+
+/// Rescans the expansion of a macro, with the macro itself disabled
+fn expand_body(
+    name: &str,
+    body: Vec<Token>,
+    defines: &HashMap<String, Macro>,
+    expanding: &mut Vec<String>,
+) -> Result<Vec<Token>, String> {
+    expanding.push(name.to_string());
+    let result = expand(body, defines, expanding);
+    expanding.pop();
+    result
+}
+
+/// Collects the comma separated arguments of a macro invocation, starting after the '('
+fn collect_arguments(
+    tokens: &[Token],
+    mut index: usize,
+    name: &str,
+) -> Result<(Vec<Vec<Token>>, usize), String> {
+    let mut arguments = vec![Vec::new()];
+    let mut depth = 0;
+
+    while index < tokens.len() {
+        let token = &tokens[index];
+        index += 1;
+
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") if depth == 0 => return Ok((arguments, index)),
+            Token::Punct(")") => depth -= 1,
+            Token::Punct(",") if depth == 0 => {
+                arguments.push(Vec::new());
+                continue;
+            }
+            _ => {}
+        }
+
+        arguments.last_mut().unwrap().push(token.clone());
+    }
+
+    Err(format!("Unterminated invocation of macro \"{}\"", name))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        token
+    }
+
+    fn expect(&mut self, punct: &'static str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Punct(found)) if found == punct => Ok(()),
+            Some(token) => Err(format!("Expected '{}', found {:?}", punct, token)),
+            None => Err(format!("Expected '{}' at the end of the expression", punct)),
+        }
+    }
+
+    fn ternary(&mut self) -> Result<Expression, String> {
+        let condition = self.binary(0)?;
+        if self.peek() != Some(&Token::Punct("?")) {
+            return Ok(condition);
+        }
+
+        self.index += 1;
+        let if_true = self.ternary()?;
+        self.expect(":")?;
+        let if_false = self.ternary()?;
+        Ok(Expression::Ternary(
+            Box::new(condition),
+            Box::new(if_true),
+            Box::new(if_false),
+        ))
+    }
+
+    /// Precedence climbing over the binary operators, higher precedence binds tighter
+    fn binary(&mut self, min_precedence: u8) -> Result<Expression, String> {
+        let mut left = self.unary()?;
+
+        while let Some(Token::Punct(op)) = self.peek() {
+            let op = *op;
+            let Some(precedence) = binary_precedence(op) else {
+                break;
+            };
+            if precedence < min_precedence {
+                break;
+            }
+
+            self.index += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expression::Number(value)),
+            Some(Token::Punct(op @ ("+" | "-" | "!" | "~"))) => {
+                Ok(Expression::Unary(op, Box::new(self.unary()?)))
+            }
+            Some(Token::Punct("(")) => {
+                let inner = self.ternary()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Some(token) => Err(format!("Unexpected token {:?} in expression", token)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn binary_precedence(op: &str) -> Option<u8> {
+    let precedence = match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    };
+    Some(precedence)
+}
+
+impl Expression {
+    fn evaluate(&self) -> Result<i64, String> {
+        let value = match self {
+            Expression::Number(value) => *value,
+            Expression::Unary(op, inner) => {
+                let inner = inner.evaluate()?;
+                match *op {
+                    "+" => inner,
+                    "-" => inner.wrapping_neg(),
+                    "!" => (inner == 0) as i64,
+                    _ => !inner,
+                }
+            }
+            Expression::Ternary(condition, if_true, if_false) => {
+                if condition.evaluate()? != 0 {
+                    if_true.evaluate()?
+                } else {
+                    if_false.evaluate()?
+                }
+            }
+            // Logical operators short circuit, so `0 && 1 / 0` is still valid
+            Expression::Binary("&&", left, right) => {
+                (left.evaluate()? != 0 && right.evaluate()? != 0) as i64
+            }
+            Expression::Binary("||", left, right) => {
+                (left.evaluate()? != 0 || right.evaluate()? != 0) as i64
+            }
+            Expression::Binary(op, left, right) => {
+                let left = left.evaluate()?;
+                let right = right.evaluate()?;
+                match *op {
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "&" => left & right,
+                    "==" => (left == right) as i64,
+                    "!=" => (left != right) as i64,
+                    "<" => (left < right) as i64,
+                    ">" => (left > right) as i64,
+                    "<=" => (left <= right) as i64,
+                    ">=" => (left >= right) as i64,
+                    "<<" => left.wrapping_shl(right as u32),
+                    ">>" => left.wrapping_shr(right as u32),
+                    "+" => left.wrapping_add(right),
+                    "-" => left.wrapping_sub(right),
+                    "*" => left.wrapping_mul(right),
+                    "/" | "%" if right == 0 => {
+                        return Err("Division by zero in expression".to_string());
+                    }
+                    "/" => left.wrapping_div(right),
+                    _ => left.wrapping_rem(right),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defines(macros: &[(&str, Option<&[&str]>, &str)]) -> HashMap<String, Macro> {
+        macros
+            .iter()
+            .map(|(name, params, body)| {
+                let params =
+                    params.map(|params| params.iter().map(|param| param.to_string()).collect());
+                (
+                    name.to_string(),
+                    Macro {
+                        params,
+                        body: body.to_string(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn follows_the_c_precedence() {
+        let defines = HashMap::new();
+        assert_eq!(evaluate("1 + 2 * 3", &defines), Ok(7));
+        assert_eq!(evaluate("(1 + 2) * 3", &defines), Ok(9));
+        assert_eq!(evaluate("1 << 2 + 1", &defines), Ok(8));
+        assert_eq!(evaluate("1 | 2 & 3 == 3", &defines), Ok(1));
+        assert_eq!(evaluate("-2 - -3", &defines), Ok(1));
+        assert_eq!(evaluate("!0 + ~0", &defines), Ok(0));
+        assert_eq!(evaluate("0 ? 1 : 2 ? 3 : 4", &defines), Ok(3));
+        assert_eq!(evaluate("0x10 + 010u", &defines), Ok(24));
+    }
+
+    #[test]
+    fn expands_macros_and_defined() {
+        let defines = defines(&[
+            ("COUNT", None, "4"),
+            ("DOUBLE", Some(&["x"]), "((x) * 2)"),
+            ("NESTED", None, "DOUBLE(COUNT) + 1"),
+        ]);
+        assert_eq!(evaluate("NESTED", &defines), Ok(9));
+        assert_eq!(
+            evaluate("defined(COUNT) && defined DOUBLE", &defines),
+            Ok(1)
+        );
+        assert_eq!(evaluate("defined(MISSING)", &defines), Ok(0));
+        assert_eq!(evaluate("MISSING + 1", &defines), Ok(1));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let defines = HashMap::new();
+        assert_eq!(evaluate("0 && 1 / 0", &defines), Ok(0));
+        assert_eq!(evaluate("1 || 1 % 0", &defines), Ok(1));
+        assert!(evaluate("1 / 0", &defines).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        let defines = HashMap::new();
+        assert!(evaluate("", &defines).is_err());
+        assert!(evaluate("(1 + 2", &defines).is_err());
+        assert!(evaluate("1 +", &defines).is_err());
+        assert!(evaluate("1 ? 2", &defines).is_err());
+        assert!(evaluate("1 2", &defines).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_macro_uses() {
+        let defines = defines(&[("ADD", Some(&["a", "b"]), "a + b")]);
+        assert!(evaluate("ADD(1)", &defines).is_err());
+        assert_eq!(evaluate("ADD(1, (2 + 3))", &defines), Ok(6));
+    }
+
+    #[test]
+    fn constants_need_every_name_to_be_a_macro() {
+        let defines = defines(&[("COUNT", None, "3"), ("DOUBLE", Some(&["x"]), "((x) * 2)")]);
+        assert_eq!(evaluate_constant("DOUBLE(COUNT) + 1", &defines), Some(7));
+        assert_eq!(evaluate_constant("N", &defines), None);
+        assert_eq!(evaluate_constant("COUNT * N", &defines), None);
+        assert_eq!(evaluate_constant("DOUBLE", &defines), None);
+        assert_eq!(evaluate_constant("1 / 0", &defines), None);
+    }
+
+    #[test]
+    fn self_referencing_macros_stop_at_their_own_name() {
+        let defines = defines(&[
+            ("FOO", None, "FOO"),
+            ("LOOP", None, "LOOP + 1"),
+            ("PING", None, "PONG"),
+            ("PONG", None, "PING * 2 + 3"),
+            ("SAME", Some(&["x"]), "(x)"),
+        ]);
+        assert_eq!(evaluate("FOO", &defines), Ok(0));
+        assert_eq!(evaluate("LOOP", &defines), Ok(1));
+        assert_eq!(evaluate("PING", &defines), Ok(3));
+        // The arguments are expanded before the macro is disabled
+        assert_eq!(evaluate("SAME(SAME(4))", &defines), Ok(4));
+        assert_eq!(evaluate_constant("FOO", &defines), None);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::shader::preprocessor::ProcessedUniform;
+use gl::types::{GLchar, GLenum, GLint, GLsizei, GLuint};
+use std::collections::HashSet;
+
+/// A uniform of the default block, as the linked program reports it
+#[derive(Clone, Debug)]
+pub struct ActiveUniform {
+    /// Name without the `[0]` GL adds to arrays, members of structs keep their full path like `light.color`
+    pub name: String,
+    pub gl_type: GLenum,
+    /// Number of elements, 1 for uniforms that aren't arrays
+    pub array_size: usize,
+    pub location: GLint,
+    is_array: bool,
+}
+
+/// A variable inside a uniform block or a shader storage block
+#[derive(Clone, Debug)]
+pub struct BlockMember {
+    pub name: String,
+    pub gl_type: GLenum,
+    /// Offset from the start of the block, in bytes
+    pub offset: usize,
+    pub array_size: usize,
+    pub array_stride: usize,
+    pub matrix_stride: usize,
+    /// Stride of the outermost array of a storage block member, 0 for uniform block members
+    pub top_level_array_stride: usize,
+}
+
+/// A uniform block or a shader storage block
+#[derive(Clone, Debug)]
+pub struct ActiveBlock {
+    pub name: String,
+    pub index: GLuint,
+    pub binding: GLuint,
+    /// Binding the block was declared with when the engine moved it to `binding`, see `assign_unique_bindings`
+    pub moved_from: Option<GLuint>,
+    /// Minimum size of the buffer bound to the block, in bytes
+    pub data_size: usize,
+    pub members: Vec<BlockMember>,
+}
+
+/// Everything a linked program exposes, read with `glGetProgramInterfaceiv` and `glGetProgramResource*`
+#[derive(Clone, Debug, Default)]
+pub struct ProgramInterface {
+    pub uniforms: Vec<ActiveUniform>,
+    pub uniform_blocks: Vec<ActiveBlock>,
+    pub storage_blocks: Vec<ActiveBlock>,
+}
+
+impl ProgramInterface {
+    /// `explicit_bindings` are the blocks declared with `layout(binding = N)`, they keep their binding
+    pub(crate) fn query(program: GLuint, explicit_bindings: &HashSet<String>) -> ProgramInterface {
+        let mut uniforms = Vec::new();
+        for index in 0..active_resources(program, gl::UNIFORM) {
+            let [gl_type, array_size, location, block_index] = resource_properties(
+                program,
+                gl::UNIFORM,
+                index,
+                [gl::TYPE, gl::ARRAY_SIZE, gl::LOCATION, gl::BLOCK_INDEX],
+            );
+
+            // Uniforms inside blocks are listed with their block
+            if block_index != -1 {
+                continue;
+            }
+
+            let name = resource_name(program, gl::UNIFORM, index);
+            let is_array = name.ends_with("[0]");
+            uniforms.push(ActiveUniform {
+                name: name.trim_end_matches("[0]").to_string(),
+                gl_type: gl_type as GLenum,
+                array_size: array_size.max(1) as usize,
+                location,
+                is_array,
+            });
+        }
+
+        let mut uniform_blocks = query_blocks(program, gl::UNIFORM_BLOCK, gl::UNIFORM);
+        let mut storage_blocks =
+            query_blocks(program, gl::SHADER_STORAGE_BLOCK, gl::BUFFER_VARIABLE);
+        assign_unique_bindings(
+            program,
+            gl::UNIFORM_BLOCK,
+            &mut uniform_blocks,
+            explicit_bindings,
+        );
+        assign_unique_bindings(
+            program,
+            gl::SHADER_STORAGE_BLOCK,
+            &mut storage_blocks,
+            explicit_bindings,
+        );
+
+        ProgramInterface {
+            uniforms,
+            uniform_blocks,
+            storage_blocks,
+        }
+    }
+
+    pub fn uniform_block(&self, name: &str) -> Option<&ActiveBlock> {
+        self.uniform_blocks.iter().find(|block| block.name == name)
+    }
+
+    pub fn storage_block(&self, name: &str) -> Option<&ActiveBlock> {
+        self.storage_blocks.iter().find(|block| block.name == name)
+    }
+
+    /// Number of elements of an array uniform, struct arrays are counted from their members like `lights[2].color`.
+    /// None when the uniform isn't an array or isn't active
+    pub(crate) fn array_size(&self, name: &str) -> Option<usize> {
+        if let Some(uniform) = self.uniforms.iter().find(|uniform| uniform.name == name) {
+            return uniform.is_array.then_some(uniform.array_size);
+        }
+
+        let prefix = format!("{}[", name);
+        self.uniforms
+            .iter()
+            .filter_map(|uniform| uniform.name.strip_prefix(&prefix))
+            .filter_map(|rest| rest.split_once(']'))
+            .filter_map(|(index, _)| index.parse::<usize>().ok())
+            .max()
+            .map(|index| index + 1)
+    }
+}
+
+impl ActiveUniform {
+    /// Same description the preprocessor gives, so both lists are linked the same way
+    pub(crate) fn to_processed(&self) -> ProcessedUniform {
+        let ty = match glsl_type_name(self.gl_type) {
+            Some(name) => name.to_string(),
+            None => format!("0x{:04X}", self.gl_type),
+        };
+
+        ProcessedUniform {
+            name: self.name.clone(),
+            ty,
+            array_size: self.is_array.then_some(self.array_size),
+            layout: Vec::new(),
+            memory: Vec::new(),
+            precision: None,
+        }
+    }
+}
+
+/// Blocks without `layout(binding = N)` all start at binding 0, so buffers bound by name would replace each other.
+/// Every such block sharing the binding of another block is moved to the lowest free binding point,
+/// blocks with an explicit binding are never moved
+fn assign_unique_bindings(
+    program: GLuint,
+    interface: GLenum,
+    blocks: &mut [ActiveBlock],
+    explicit_bindings: &HashSet<String>,
+) {
+    // Arrays of blocks are reported per element, like `Lights[0]`
+    let is_explicit = |block: &ActiveBlock| {
+        let name = block.name.split('[').next().unwrap_or_default();
+        explicit_bindings.contains(name)
+    };
+
+    let mut used = blocks
+        .iter()
+        .filter(|block| is_explicit(block))
+        .map(|block| block.binding)
+        .collect::<HashSet<_>>();
+    let clashing = blocks
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| !is_explicit(block) && !used.insert(block.binding))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    let mut free = 0;
+    for index in clashing {
+        while used.contains(&free) {
+            free += 1;
+        }
+        used.insert(free);
+
+        let block = &mut blocks[index];
+        block.moved_from = Some(block.binding);
+        block.binding = free;
+        unsafe {
+            match interface {
+                gl::UNIFORM_BLOCK => gl::UniformBlockBinding(program, block.index, free),
+                _ => gl::ShaderStorageBlockBinding(program, block.index, free),
+            }
+        }
+    }
+}
+
+fn query_blocks(program: GLuint, interface: GLenum, member_interface: GLenum) -> Vec<ActiveBlock> {
+    let mut blocks = Vec::new();
+    for index in 0..active_resources(program, interface) {
+        let [binding, data_size, member_count] = resource_properties(
+            program,
+            interface,
+            index,
+            [
+                gl::BUFFER_BINDING,
+                gl::BUFFER_DATA_SIZE,
+                gl::NUM_ACTIVE_VARIABLES,
+            ],
+        );
+
+        // The members are given as indices into the uniform or the buffer variable interface
+        let mut member_indices = vec![0; member_count.max(0) as usize];
+        unsafe {
+            gl::GetProgramResourceiv(
+                program,
+                interface,
+                index,
+                1,
+                &gl::ACTIVE_VARIABLES,
+                member_indices.len() as GLsizei,
+                std::ptr::null_mut(),
+                member_indices.as_mut_ptr(),
+            );
+        }
+
+        let mut members = member_indices
+            .into_iter()
+            .map(|member| {
+                let member = member as GLuint;
+                let [gl_type, offset, array_size, array_stride, matrix_stride] =
+                    resource_properties(
+                        program,
+                        member_interface,
+                        member,
+                        [
+                            gl::TYPE,
+                            gl::OFFSET,
+                            gl::ARRAY_SIZE,
+                            gl::ARRAY_STRIDE,
+                            gl::MATRIX_STRIDE,
+                        ],
+                    );
+
+                // Only buffer variables know about the outermost array, like the runtime array of a storage block
+                let top_level_array_stride = if member_interface == gl::BUFFER_VARIABLE {
+                    let [stride] = resource_properties(
+                        program,
+                        member_interface,
+                        member,
+                        [gl::TOP_LEVEL_ARRAY_STRIDE],
+                    );
+                    stride.max(0) as usize
+                } else {
+                    0
+                };
+
+                BlockMember {
+                    name: resource_name(program, member_interface, member),
+                    gl_type: gl_type as GLenum,
+                    offset: offset.max(0) as usize,
+                    array_size: array_size.max(1) as usize,
+                    array_stride: array_stride.max(0) as usize,
+                    matrix_stride: matrix_stride.max(0) as usize,
+                    top_level_array_stride,
+                }
+            })
+            .collect::<Vec<_>>();
+        members.sort_by_key(|member| member.offset);
+
+        blocks.push(ActiveBlock {
+            name: resource_name(program, interface, index),
+            index,
+            binding: binding.max(0) as GLuint,
+            moved_from: None,
+            data_size: data_size.max(0) as usize,
+            members,
+        });
+    }
+    blocks
+}
+
+fn active_resources(program: GLuint, interface: GLenum) -> GLuint {
+    let mut count: GLint = 0;
+    unsafe {
+        gl::GetProgramInterfaceiv(program, interface, gl::ACTIVE_RESOURCES, &mut count);
+    }
+    count.max(0) as GLuint
+}
+
+fn resource_properties<const N: usize>(
+    program: GLuint,
+    interface: GLenum,
+    index: GLuint,
+    properties: [GLenum; N],
+) -> [GLint; N] {
+    let mut values = [0; N];
+    unsafe {
+        gl::GetProgramResourceiv(
+            program,
+            interface,
+            index,
+            N as GLsizei,
+            properties.as_ptr(),
+            N as GLsizei,
+            std::ptr::null_mut(),
+            values.as_mut_ptr(),
+        );
+    }
+    values
+}
+
+fn resource_name(program: GLuint, interface: GLenum, index: GLuint) -> String {
+    // The length includes the null terminator
+    let [length] = resource_properties(program, interface, index, [gl::NAME_LENGTH]);
+    if length <= 0 {
+        return String::new();
+    }
+
+    let mut buffer: Vec<u8> = vec![0; length as usize];
+    let mut written: GLsizei = 0;
+    unsafe {
+        gl::GetProgramResourceName(
+            program,
+            interface,
+            index,
+            length,
+            &mut written,
+            buffer.as_mut_ptr() as *mut GLchar,
+        );
+    }
+    buffer.truncate(written.max(0) as usize);
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+/// GLSL name of a type reported by GL, None for the types the engine doesn't know
+pub(crate) fn glsl_type_name(gl_type: GLenum) -> Option<&'static str> {
+    let name = match gl_type {
+        gl::BOOL => "bool",
+        gl::INT => "int",
+        gl::UNSIGNED_INT => "uint",
+        gl::FLOAT => "float",
+        gl::DOUBLE => "double",
+
+        gl::BOOL_VEC2 => "bvec2",
+        gl::BOOL_VEC3 => "bvec3",
+        gl::BOOL_VEC4 => "bvec4",
+        gl::INT_VEC2 => "ivec2",
+        gl::INT_VEC3 => "ivec3",
+        gl::INT_VEC4 => "ivec4",
+        gl::UNSIGNED_INT_VEC2 => "uvec2",
+        gl::UNSIGNED_INT_VEC3 => "uvec3",
+        gl::UNSIGNED_INT_VEC4 => "uvec4",
+        gl::FLOAT_VEC2 => "vec2",
+        gl::FLOAT_VEC3 => "vec3",
+        gl::FLOAT_VEC4 => "vec4",
+        gl::DOUBLE_VEC2 => "dvec2",
+        gl::DOUBLE_VEC3 => "dvec3",
+        gl::DOUBLE_VEC4 => "dvec4",
+
+        gl::FLOAT_MAT2 => "mat2",
+        gl::FLOAT_MAT3 => "mat3",
+        gl::FLOAT_MAT4 => "mat4",
+        gl::FLOAT_MAT2x3 => "mat2x3",
+        gl::FLOAT_MAT2x4 => "mat2x4",
+        gl::FLOAT_MAT3x2 => "mat3x2",
+        gl::FLOAT_MAT3x4 => "mat3x4",
+        gl::FLOAT_MAT4x2 => "mat4x2",
+        gl::FLOAT_MAT4x3 => "mat4x3",
+        gl::DOUBLE_MAT2 => "dmat2",
+        gl::DOUBLE_MAT3 => "dmat3",
+        gl::DOUBLE_MAT4 => "dmat4",
+        gl::DOUBLE_MAT2x3 => "dmat2x3",
+        gl::DOUBLE_MAT2x4 => "dmat2x4",
+        gl::DOUBLE_MAT3x2 => "dmat3x2",
+        gl::DOUBLE_MAT3x4 => "dmat3x4",
+        gl::DOUBLE_MAT4x2 => "dmat4x2",
+        gl::DOUBLE_MAT4x3 => "dmat4x3",
+
+        gl::SAMPLER_1D => "sampler1D",
+        gl::SAMPLER_2D => "sampler2D",
+        gl::SAMPLER_3D => "sampler3D",
+        gl::SAMPLER_CUBE => "samplerCube",
+        gl::SAMPLER_1D_ARRAY => "sampler1DArray",
+        gl::SAMPLER_2D_ARRAY => "sampler2DArray",
+        gl::SAMPLER_BUFFER => "samplerBuffer",
+        gl::INT_SAMPLER_2D => "isampler2D",
+        gl::INT_SAMPLER_3D => "isampler3D",
+        gl::UNSIGNED_INT_SAMPLER_2D => "usampler2D",
+        gl::UNSIGNED_INT_SAMPLER_3D => "usampler3D",
+
+        gl::IMAGE_1D => "image1D",
+        gl::IMAGE_2D => "image2D",
+        gl::IMAGE_3D => "image3D",
+        gl::IMAGE_CUBE => "imageCube",
+        gl::IMAGE_2D_ARRAY => "image2DArray",
+        gl::IMAGE_BUFFER => "imageBuffer",
+        gl::INT_IMAGE_2D => "iimage2D",
+        gl::INT_IMAGE_3D => "iimage3D",
+        gl::UNSIGNED_INT_IMAGE_2D => "uimage2D",
+        gl::UNSIGNED_INT_IMAGE_3D => "uimage3D",
+        _ => return None,
+    };
+    Some(name)
+}
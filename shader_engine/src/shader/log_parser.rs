@@ -0,0 +1,416 @@
+use crate::shader::diagnostic::{Diagnostic, Severity};
+use crate::shader::preprocessor::ProcessData;
+use regex::Regex;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+/// `0(12) : error C0000: message`
+const NVIDIA_PATTERN: &str =
+    r"^(\d+)\((\d+)\) ?: ((?:fatal |internal )?error|warning|info)(?: \w+)?: (.*)$";
+/// `0:12(5): error: message`, also `0:12(5): preprocessor error: message`
+const MESA_PATTERN: &str = r"^(\d+):(\d+)\((\d+)\): (?:preprocessor )?(error|warning|info): (.*)$";
+/// `ERROR: 0:12: message`, used by AMD, Intel and Apple, sometimes with a column after the line
+const AMD_PATTERN: &str = r"^(ERROR|WARNING|INFO): (\d+):(\d+):(?:(\d+):)? (.*)$";
+
+/// `Vertex info`, `Fragment info`... headers NVIDIA puts before the messages of each stage in link logs
+const STAGE_HEADER_PATTERN: &str = r"(?i)^(vertex|fragment|compute|geometry|tessellation control|tessellation evaluation)(?: shader)? info$";
+/// `ERROR: Linking fragment stage: message`, written by glslang based drivers
+const LINKING_STAGE_PATTERN: &str = r"(?i)^(?:(error|warning)\s*:\s*)?linking (vertex|fragment|compute|geometry|tessellation control|tessellation evaluation) stage:\s*(.*)$";
+/// `error: message`, most link messages have a severity but no position
+const SEVERITY_PREFIX_PATTERN: &str = r"(?i)^(fatal error|error|warning|info)\s*:\s*(.*)$";
+/// Identifiers quoted in a message, like `name', 'name' or "name"
+const QUOTED_IDENTIFIER_PATTERN: &str = r#"[`'"]([A-Za-z_]\w*)['"]"#;
+
+/// A message read from one line of a driver log, the position is still in the processed source
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    /// Source string number, the file index when `#line` directives are used
+    pub source: usize,
+    pub line: usize,
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Reads the lines of a shader info log, each driver family has its own format.
+/// Custom parsers can be added to the ShaderOptions, they are tried before the built-in ones
+pub trait LogParser: Debug {
+    /// Returns None when the line is not in the format of this parser
+    fn parse_line(&self, line: &str) -> Option<LogEntry>;
+}
+
+/// The parsers every ShaderOptions starts with
+pub fn default_log_parsers() -> Vec<Rc<dyn LogParser>> {
+    vec![
+        Rc::new(NvidiaLogParser::new()),
+        Rc::new(MesaLogParser::new()),
+        Rc::new(AmdLogParser::new()),
+    ]
+}
+
+fn parse_severity(text: &str) -> Severity {
+    let lowercase = text.to_lowercase();
+    if lowercase.contains("error") {
+        Severity::Error
+    } else if lowercase == "warning" {
+        Severity::Warning
+    } else {
+        Severity::Info
+    }
+}
+
+#[derive(Debug)]
+pub struct NvidiaLogParser {
+    pattern: Regex,
+}
+
+impl NvidiaLogParser {
+    pub fn new() -> NvidiaLogParser {
+        NvidiaLogParser {
+            pattern: Regex::new(NVIDIA_PATTERN).unwrap(),
+        }
+    }
+}
+
+impl Default for NvidiaLogParser {
+    fn default() -> Self {
+        NvidiaLogParser::new()
+    }
+}
+
+impl LogParser for NvidiaLogParser {
+    fn parse_line(&self, line: &str) -> Option<LogEntry> {
+        let capture = self.pattern.captures(line)?;
+        Some(LogEntry {
+            source: capture[1].parse().ok()?,
+            line: capture[2].parse().ok()?,
+            column: None,
+            severity: parse_severity(&capture[3]),
+            message: capture[4].to_string(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct MesaLogParser {
+    pattern: Regex,
+}
+
+impl MesaLogParser {
+    pub fn new() -> MesaLogParser {
+        MesaLogParser {
+            pattern: Regex::new(MESA_PATTERN).unwrap(),
+        }
+    }
+}
+
+impl Default for MesaLogParser {
+    fn default() -> Self {
+        MesaLogParser::new()
+    }
+}
+
+impl LogParser for MesaLogParser {
+    fn parse_line(&self, line: &str) -> Option<LogEntry> {
+        let capture = self.pattern.captures(line)?;
+        Some(LogEntry {
+            source: capture[1].parse().ok()?,
+            line: capture[2].parse().ok()?,
+            column: capture[3].parse().ok(),
+            severity: parse_severity(&capture[4]),
+            message: capture[5].to_string(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct AmdLogParser {
+    pattern: Regex,
+}
+
+impl AmdLogParser {
+    pub fn new() -> AmdLogParser {
+        AmdLogParser {
+            pattern: Regex::new(AMD_PATTERN).unwrap(),
+        }
+    }
+}
+
+impl Default for AmdLogParser {
+    fn default() -> Self {
+        AmdLogParser::new()
+    }
+}
+
+impl LogParser for AmdLogParser {
+    fn parse_line(&self, line: &str) -> Option<LogEntry> {
+        let capture = self.pattern.captures(line)?;
+        Some(LogEntry {
+            source: capture[2].parse().ok()?,
+            line: capture[3].parse().ok()?,
+            column: capture
+                .get(4)
+                .and_then(|column| column.as_str().parse().ok()),
+            severity: parse_severity(&capture[1]),
+            message: capture[5].to_string(),
+        })
+    }
+}
+
+/// Turns a whole info log into diagnostics, positions are mapped back to the original files.
+/// Lines no parser understands are kept as raw diagnostics
+pub(crate) fn parse_log(
+    log: &str,
+    parsers: &[Rc<dyn LogParser>],
+    data: &ProcessData,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for line in log.lines() {
+        // Skip the null terminator and the empty lines
+        let line = line.trim_end_matches('\0').trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let Some(entry) = parsers.iter().find_map(|parser| parser.parse_line(line)) else {
+            diagnostics.push(Diagnostic::raw(line));
+            continue;
+        };
+
+        // A column without a known line means nothing
+        let position = data.resolve(entry.source, entry.line);
+        diagnostics.push(Diagnostic {
+            column: position.as_ref().and(entry.column),
+            file: position.as_ref().map(|(file, _)| file.clone()),
+            line: position.map(|(_, line)| line),
+            severity: entry.severity,
+            message: entry.message,
+            stage: None,
+        });
+    }
+
+    diagnostics
+}
+
+/// A shader attached to a program, as seen by the link log parser
+pub(crate) struct LinkedStage<'a> {
+    pub(crate) name: &'static str,
+    pub(crate) data: &'a ProcessData,
+}
+
+/// Turns the info log of a program into diagnostics.
+/// Link messages rarely have a position, so they are tied to the stages they mention
+/// and to the file that uses the first identifier they quote
+pub(crate) fn parse_link_log(
+    log: &str,
+    parsers: &[Rc<dyn LogParser>],
+    stages: &[LinkedStage],
+) -> Vec<Diagnostic> {
+    let stage_header = Regex::new(STAGE_HEADER_PATTERN).unwrap();
+    let linking_stage = Regex::new(LINKING_STAGE_PATTERN).unwrap();
+    let severity_prefix = Regex::new(SEVERITY_PREFIX_PATTERN).unwrap();
+    let quoted_identifier = Regex::new(QUOTED_IDENTIFIER_PATTERN).unwrap();
+
+    let find_stage = |name: &str| {
+        stages
+            .iter()
+            .find(|stage| stage.name.eq_ignore_ascii_case(name))
+    };
+
+    let mut diagnostics = Vec::new();
+    let mut current_stage = None;
+    for line in log.lines() {
+        // Skip the null terminator, the empty lines and the underlines of the headers
+        let line = line.trim_end_matches('\0').trim();
+        if line.is_empty() || line.chars().all(|char| char == '-') {
+            continue;
+        }
+
+        if let Some(header) = stage_header.captures(line) {
+            current_stage = find_stage(&header[1]);
+            continue;
+        }
+
+        let mut message = line.to_string();
+        if let Some(linking) = linking_stage.captures(line) {
+            current_stage = find_stage(&linking[2]);
+            if linking[3].is_empty() {
+                continue;
+            }
+
+            // The severity stays in front of the message
+            message = match linking.get(1) {
+                Some(severity) => format!("{}: {}", severity.as_str(), &linking[3]),
+                None => linking[3].to_string(),
+            };
+        }
+
+        // Positioned messages belong to the current stage, or to the only one there is
+        let positioned_stage = current_stage.or(if stages.len() == 1 {
+            stages.first()
+        } else {
+            None
+        });
+        let entry = parsers
+            .iter()
+            .find_map(|parser| parser.parse_line(&message));
+        if let (Some(stage), Some(entry)) = (positioned_stage, entry) {
+            let position = stage.data.resolve(entry.source, entry.line);
+            diagnostics.push(Diagnostic {
+                column: position.as_ref().and(entry.column),
+                file: position.as_ref().map(|(file, _)| file.clone()),
+                line: position.map(|(_, line)| line),
+                severity: entry.severity,
+                message: entry.message,
+                stage: Some(stage.name.to_string()),
+            });
+            continue;
+        }
+
+        let (severity, text) = match severity_prefix.captures(&message) {
+            Some(prefix) => (parse_severity(&prefix[1]), prefix[2].to_string()),
+            None => (Severity::guess(&message), message.clone()),
+        };
+
+        // Stages named in the message, in the order they are named
+        let lowercase = text.to_lowercase();
+        let mut mentioned = stages
+            .iter()
+            .filter_map(|stage| lowercase.find(stage.name).map(|index| (index, stage)))
+            .collect::<Vec<_>>();
+        mentioned.sort_by_key(|(index, _)| *index);
+
+        // The identifier is searched in the named stages first, then in the rest
+        let search_order = mentioned
+            .iter()
+            .map(|(_, stage)| *stage)
+            .chain(current_stage)
+            .chain(stages.iter());
+        let found = quoted_identifier.captures(&text).and_then(|identifier| {
+            search_order.clone().find_map(|stage| {
+                let position = stage.data.find_identifier(&identifier[1])?;
+                Some((stage, position))
+            })
+        });
+
+        let stage = found
+            .as_ref()
+            .map(|(stage, _)| *stage)
+            .or(mentioned.first().map(|(_, stage)| *stage))
+            .or(current_stage);
+        let position = found.map(|(_, position)| position);
+        diagnostics.push(Diagnostic {
+            file: position.as_ref().map(|(file, _)| file.clone()),
+            line: position.map(|(_, line)| line),
+            column: None,
+            severity,
+            message: text,
+            stage: stage.map(|stage| stage.name.to_string()),
+        });
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::options::ShaderOptions;
+    use crate::shader::preprocessor::process_shader;
+    use crate::shader::source::MemorySource;
+    use crate::utils::html_logger::HTMLLogger;
+
+    fn position(entry: &LogEntry) -> (usize, usize, Option<usize>, Severity) {
+        (entry.source, entry.line, entry.column, entry.severity)
+    }
+
+    #[test]
+    fn nvidia_lines() {
+        let parser = NvidiaLogParser::new();
+        let entry = parser
+            .parse_line("0(12) : error C1008: undefined variable \"x\"")
+            .unwrap();
+        assert_eq!(position(&entry), (0, 12, None, Severity::Error));
+        assert_eq!(entry.message, "undefined variable \"x\"");
+
+        let entry = parser
+            .parse_line("2(7) : warning C7050: \"y\" might be used before being initialized")
+            .unwrap();
+        assert_eq!(position(&entry), (2, 7, None, Severity::Warning));
+
+        let entry = parser
+            .parse_line("1(3): fatal error C9999: out of memory")
+            .unwrap();
+        assert_eq!(position(&entry), (1, 3, None, Severity::Error));
+
+        assert!(parser.parse_line("0:12(5): error: x undeclared").is_none());
+    }
+
+    #[test]
+    fn mesa_lines() {
+        let parser = MesaLogParser::new();
+        let entry = parser.parse_line("0:12(5): error: `x' undeclared").unwrap();
+        assert_eq!(position(&entry), (0, 12, Some(5), Severity::Error));
+        assert_eq!(entry.message, "`x' undeclared");
+
+        let entry = parser
+            .parse_line("1:4(10): preprocessor error: Unterminated #if")
+            .unwrap();
+        assert_eq!(position(&entry), (1, 4, Some(10), Severity::Error));
+
+        let entry = parser.parse_line("0:8(1): warning: unused").unwrap();
+        assert_eq!(position(&entry), (0, 8, Some(1), Severity::Warning));
+
+        assert!(parser.parse_line("ERROR: 0:12: x undeclared").is_none());
+    }
+
+    #[test]
+    fn amd_lines() {
+        let parser = AmdLogParser::new();
+        let entry = parser
+            .parse_line("ERROR: 0:12: 'x' : undeclared identifier")
+            .unwrap();
+        assert_eq!(position(&entry), (0, 12, None, Severity::Error));
+        assert_eq!(entry.message, "'x' : undeclared identifier");
+
+        let entry = parser.parse_line("WARNING: 3:40:7: unused").unwrap();
+        assert_eq!(position(&entry), (3, 40, Some(7), Severity::Warning));
+
+        assert!(parser.parse_line("0(12) : error C1008: x").is_none());
+    }
+
+    #[test]
+    fn log_positions_point_to_the_included_files() {
+        let source = MemorySource::new()
+            .with_file(
+                "main.comp",
+                "#version 450\n#include \"common.glsl\"\nvoid main() {}\n",
+            )
+            .with_file("common.glsl", "float a;\nfloat b;\n");
+        let options = ShaderOptions::new().source(source).line_directives(true);
+        let data = process_shader(&mut HTMLLogger::new("test"), "main.comp", &options).unwrap();
+
+        let log = "0:3(1): error: in main\n1:2(7): warning: in common\nsomething else\0";
+        let diagnostics = parse_log(log, &default_log_parsers(), &data);
+        let positions = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.file.as_deref(),
+                    diagnostic.line,
+                    diagnostic.column,
+                    diagnostic.severity,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [
+                (Some("main.comp"), Some(3), Some(1), Severity::Error),
+                (Some("common.glsl"), Some(2), Some(7), Severity::Warning),
+                (None, None, None, Severity::Info),
+            ]
+        );
+    }
+}
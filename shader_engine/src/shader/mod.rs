@@ -1,25 +1,50 @@
-mod bindable;
+pub mod barrier;
+pub(crate) mod bindable;
+pub mod diagnostic;
 mod error_handler;
+mod expression;
+pub mod introspection;
+pub mod log_parser;
+pub mod options;
 mod preprocessor;
+pub(crate) mod record;
 mod shader_gen;
+pub mod source;
 mod uniform;
 
+use crate::buffer::layout::{validate_block, validate_storage_block};
+use crate::buffer::{BlockLayout, LayoutRules, StorageBuffer, UniformBuffer};
 use crate::quote;
-use crate::shader::shader_gen::Shader;
+use crate::shader::barrier::MemoryBarrier;
+use crate::shader::diagnostic::{Diagnostic, ShaderError};
+use crate::shader::error_handler::{has_warnings, log_diagnostics, read_info_log};
+use crate::shader::introspection::ProgramInterface;
+use crate::shader::log_parser::{parse_link_log, LinkedStage};
+use crate::shader::options::{ShaderOptions, UniformDiscovery};
+use crate::shader::preprocessor::ProcessedUniform;
+use crate::shader::record::{hash_source, register_program, ProgramRecord};
+use crate::shader::shader_gen::{stage_name, Shader};
 use crate::shader::uniform::{Uniform, UniformVariable};
 use crate::utils::colorized_text::Colorize;
 use crate::utils::html_logger::{Details, HTMLLogger, Summary};
-use gl::types::{GLchar, GLint, GLuint};
+use gl::types::{GLint, GLintptr, GLuint};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CString;
-use std::ptr;
+use std::fmt::Display;
 use std::rc::Rc;
 
+pub use crate::shader::uniform::{UniformField, UniformStruct};
+pub use shader_engine_derive::UniformStruct;
+
 pub trait ShaderType {}
 
 pub struct ComputeShader {
     compute_file: String,
+    /// `local_size_x/y/z` of the linked program
+    local_size: [u32; 3],
+    /// Barrier guessed from the interface, used unless the options set one
+    barrier: MemoryBarrier,
 }
 
 pub struct GraphicsShader {
@@ -33,11 +58,18 @@ impl ShaderType for GraphicsShader {}
 pub struct ShaderProgram<T> {
     name: String,
     type_data: T,
+    options: ShaderOptions,
     pub(crate) id: GLuint,
     using: bool,
     shaders: Vec<Shader>,
     uniforms: HashMap<String, Rc<RefCell<dyn Uniform>>>,
     images: HashMap<String, GLint>,
+    link_diagnostics: Vec<Diagnostic>,
+    interface: ProgramInterface,
+    /// Error of the last failed reload, cleared once a reload succeeds
+    last_error: Option<ShaderError>,
+    /// Sources and uniforms written next to screenshots
+    record: Rc<RefCell<ProgramRecord>>,
 }
 
 impl ShaderProgram<GraphicsShader> {
@@ -46,14 +78,19 @@ impl ShaderProgram<GraphicsShader> {
         name: &str,
         vertex_file: &str,
         fragment_file: &str,
-    ) -> Result<ShaderProgram<GraphicsShader>, String> {
+        options: ShaderOptions,
+    ) -> Result<ShaderProgram<GraphicsShader>, ShaderError> {
         let main_scope = logger.open_scope("Creating ".yellow() + name.magenta());
 
         let (vertex_shader, fragment_shader) =
-            match ShaderProgram::<GraphicsShader>::generate_shaders(logger, vec![
-                (vertex_file, gl::VERTEX_SHADER),
-                (fragment_file, gl::FRAGMENT_SHADER),
-            ]) {
+            match ShaderProgram::<GraphicsShader>::generate_shaders(
+                logger,
+                vec![
+                    (vertex_file, gl::VERTEX_SHADER),
+                    (fragment_file, gl::FRAGMENT_SHADER),
+                ],
+                &options,
+            ) {
                 Ok(shaders) => {
                     let mut reversed = shaders.into_iter().rev().collect::<Vec<_>>();
                     (reversed.pop().unwrap(), reversed.pop().unwrap())
@@ -80,30 +117,47 @@ impl ShaderProgram<GraphicsShader> {
             gl::LinkProgram(program);
         }
 
-        {
+        let link_diagnostics = {
             let summary = &mut main_scope.borrow_mut().summary;
-            check_program(logger, summary, program)?;
-        }
+            check_program(
+                logger,
+                summary,
+                program,
+                &[&vertex_shader, &fragment_shader],
+                &options,
+            )?
+        };
 
         unsafe {
             gl::DetachShader(program, vertex_shader.id);
             gl::DetachShader(program, fragment_shader.id);
         }
 
+        let interface = ProgramInterface::query(
+            program,
+            &explicit_bindings(&[&vertex_shader, &fragment_shader]),
+        );
+
         let mut shader_program = ShaderProgram {
             name: name.to_string(),
             type_data: GraphicsShader {
                 vertex_file: vertex_file.to_owned(),
                 fragment_file: fragment_file.to_owned(),
             },
+            options,
             id: program,
             using: false,
             shaders: Vec::from([vertex_shader, fragment_shader]),
             uniforms: HashMap::new(),
             images: HashMap::new(),
+            link_diagnostics,
+            interface,
+            last_error: None,
+            record: register_program(),
         };
 
         shader_program.link_all_uniforms(logger);
+        shader_program.update_record();
 
         logger.close_scope();
         logger.to_html();
@@ -116,13 +170,15 @@ impl ShaderProgram<ComputeShader> {
         logger: &mut HTMLLogger,
         name: &str,
         compute_file: &str,
-    ) -> Result<ShaderProgram<ComputeShader>, String> {
+        options: ShaderOptions,
+    ) -> Result<ShaderProgram<ComputeShader>, ShaderError> {
         let main_scope = logger.open_scope("Creating ".yellow() + name.magenta());
 
-        let compute_shader = match ShaderProgram::<ComputeShader>::generate_shaders(logger, vec![(
-            compute_file,
-            gl::COMPUTE_SHADER,
-        )]) {
+        let compute_shader = match ShaderProgram::<ComputeShader>::generate_shaders(
+            logger,
+            vec![(compute_file, gl::COMPUTE_SHADER)],
+            &options,
+        ) {
             Ok(mut shaders) => shaders.pop().unwrap(),
             Err(e) => {
                 main_scope
@@ -144,28 +200,38 @@ impl ShaderProgram<ComputeShader> {
             gl::LinkProgram(program);
         }
 
-        {
+        let link_diagnostics = {
             let summary = &mut main_scope.borrow_mut().summary;
-            check_program(logger, summary, program)?;
-        }
+            check_program(logger, summary, program, &[&compute_shader], &options)?
+        };
 
         unsafe {
             gl::DetachShader(program, compute_shader.id);
         }
 
+        let interface = ProgramInterface::query(program, &explicit_bindings(&[&compute_shader]));
+
         let mut shader_program = ShaderProgram {
             name: name.to_string(),
             type_data: ComputeShader {
                 compute_file: compute_file.to_owned(),
+                local_size: query_local_size(program),
+                barrier: MemoryBarrier::infer(&interface),
             },
+            options,
             id: program,
             using: false,
             shaders: Vec::from([compute_shader]),
             uniforms: HashMap::new(),
             images: HashMap::new(),
+            link_diagnostics,
+            interface,
+            last_error: None,
+            record: register_program(),
         };
 
         shader_program.link_all_uniforms(logger);
+        shader_program.update_record();
 
         logger.close_scope();
         logger.to_html();
@@ -178,13 +244,38 @@ impl<ST: ShaderType> ShaderProgram<ST> {
     fn generate_shaders(
         logger: &mut HTMLLogger,
         files_and_type: Vec<(&str, GLuint)>,
-    ) -> Result<Vec<Shader>, String> {
+        options: &ShaderOptions,
+    ) -> Result<Vec<Shader>, ShaderError> {
         files_and_type
             .iter()
-            .map(|(file, ty)| Shader::from_file(logger, file, *ty))
+            .map(|(file, ty)| Shader::from_file(logger, file, *ty, options))
+            .collect()
+    }
+
+    pub fn get_options(&self) -> &ShaderOptions {
+        &self.options
+    }
+
+    /// Warnings and messages of the compilers and the linker from the last successful build
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.shaders
+            .iter()
+            .flat_map(|shader| shader.diagnostics.iter())
+            .chain(self.link_diagnostics.iter())
+            .cloned()
             .collect()
     }
 
+    /// Active uniforms and blocks of the linked program
+    pub fn interface(&self) -> &ProgramInterface {
+        &self.interface
+    }
+
+    /// The error of the last reload, the program keeps running with its previous shaders until it is fixed
+    pub fn last_error(&self) -> Option<&ShaderError> {
+        self.last_error.as_ref()
+    }
+
     /// Toggles between on and off and when it's on, it also handles/loads the uniforms
     pub fn toggle_use(&mut self) {
         self.using = !self.using;
@@ -209,6 +300,27 @@ impl<ST: ShaderType> ShaderProgram<ST> {
         }
     }
 
+    /// Refreshes what screenshots record about the program, after it is built or reloaded
+    fn update_record(&mut self) {
+        let mut record = self.record.borrow_mut();
+        record.name = self.name.clone();
+        record.files = self
+            .shaders
+            .iter()
+            .flat_map(|shader| shader.data.sources.iter())
+            .map(|(file, contents)| (file.clone(), hash_source(contents)))
+            .collect();
+        record.files.sort();
+        record.files.dedup();
+        record.defines = self
+            .options
+            .defines
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        record.uniforms = self.uniforms.values().cloned().collect();
+    }
+
     /// Drops the shader program
     fn drop(&mut self) {
         unsafe {
@@ -219,11 +331,63 @@ impl<ST: ShaderType> ShaderProgram<ST> {
 
 // For compute shaders, we need a dispatch function
 impl ShaderProgram<ComputeShader> {
+    /// Dispatches the work groups, followed by the barrier of the program
     pub fn dispatch_compute(&mut self, x: u32, y: u32, z: u32) {
+        self.dispatch_compute_with(x, y, z, self.barrier());
+    }
+
+    /// Same as `dispatch_compute`, with the barrier of this dispatch only
+    pub fn dispatch_compute_with(&mut self, x: u32, y: u32, z: u32, barrier: MemoryBarrier) {
         unsafe {
             gl::DispatchCompute(x, y, z);
-            gl::MemoryBarrier(gl::ALL_BARRIER_BITS);
         }
+        barrier.issue();
+    }
+
+    /// Barrier issued after the dispatches, the one set on the options or the one inferred from the interface
+    pub fn barrier(&self) -> MemoryBarrier {
+        self.options.barrier.unwrap_or(self.type_data.barrier)
+    }
+
+    /// Overrides the barrier, None goes back to the inferred one
+    pub fn set_barrier(&mut self, barrier: Option<MemoryBarrier>) {
+        self.options.barrier = barrier;
+    }
+
+    /// Work group size declared in the shader, kept up to date on reloads
+    pub fn local_size(&self) -> [u32; 3] {
+        self.type_data.local_size
+    }
+
+    /// Number of work groups needed to cover `width * height * depth` invocations
+    pub fn group_count_for_size(&self, width: u32, height: u32, depth: u32) -> [u32; 3] {
+        let [x, y, z] = self.type_data.local_size;
+        [width.div_ceil(x), height.div_ceil(y), depth.div_ceil(z)]
+    }
+
+    /// Dispatches enough work groups to cover the size, shaders should still skip the invocations outside of it
+    pub fn dispatch_for_size(&mut self, width: u32, height: u32, depth: u32) {
+        let [x, y, z] = self.group_count_for_size(width, height, depth);
+        self.dispatch_compute(x, y, z);
+    }
+
+    /// Dispatches with the group counts stored at `index` in the buffer, usually written by an earlier dispatch.
+    /// `group_count_for_size` gives the counts for a size when they are written from Rust
+    pub fn dispatch_indirect(&mut self, commands: &StorageBuffer<[u32; 3]>, index: usize) {
+        assert!(
+            index < commands.len(),
+            "Index {} is out of {} commands",
+            index,
+            commands.len()
+        );
+
+        let offset = index * StorageBuffer::<[u32; 3]>::stride();
+        unsafe {
+            gl::BindBuffer(gl::DISPATCH_INDIRECT_BUFFER, commands.id());
+            gl::DispatchComputeIndirect(offset as GLintptr);
+            gl::BindBuffer(gl::DISPATCH_INDIRECT_BUFFER, 0);
+        }
+        self.barrier().issue();
     }
 }
 
@@ -232,9 +396,14 @@ impl<ST: ShaderType> ShaderProgram<ST> {
     fn handle_uniforms(&mut self, force: bool) {
         for ref_uniform in self.uniforms.values_mut() {
             let mut uniform = ref_uniform.borrow_mut();
-            if force || uniform.is_dirty() {
-                uniform.clear_dirty();
+            if force {
+                uniform.mark_dirty();
+            }
+
+            if uniform.is_dirty() {
+                // Arrays look at their dirty elements while loading, so clear afterwards
                 uniform.load_uniform();
+                uniform.clear_dirty();
             }
         }
     }
@@ -242,67 +411,244 @@ impl<ST: ShaderType> ShaderProgram<ST> {
     fn link_all_uniforms(&mut self, logger: &mut HTMLLogger) {
         logger.open_scope("Uniform Linking ".yellow());
 
-        let all_uniforms = self
+        let declared_uniforms = self
             .shaders
             .iter()
             .flat_map(|shader| shader.data.uniforms.iter())
             .cloned()
             .collect::<Vec<_>>();
 
+        let all_uniforms = match self.options.uniform_discovery {
+            // Array sizes the preprocessor couldn't evaluate are read from the program
+            UniformDiscovery::Preprocessor => declared_uniforms
+                .into_iter()
+                .map(|mut uniform| {
+                    if uniform.array_size.is_none() {
+                        uniform.array_size = self.interface.array_size(&uniform.name);
+                    }
+                    uniform
+                })
+                .collect(),
+            UniformDiscovery::Introspection => {
+                let active_uniforms = self
+                    .interface
+                    .uniforms
+                    .iter()
+                    .map(|uniform| uniform.to_processed())
+                    .collect::<Vec<_>>();
+                self.cross_check_uniforms(logger, &declared_uniforms, &active_uniforms);
+                active_uniforms
+            }
+        };
+
         for uniform in all_uniforms {
             match uniform.ty.as_str() {
                 // Literals
-                "bool" => self.add_uniform::<bool>(logger, &uniform.name, "bool", false),
-                "int" => self.add_uniform::<i32>(logger, &uniform.name, "int", 0),
-                "uint" => self.add_uniform::<u32>(logger, &uniform.name, "uint", 0),
-                "float" => self.add_uniform::<f32>(logger, &uniform.name, "float", 0.0),
-                "double" => self.add_uniform::<f64>(logger, &uniform.name, "double", 0.0),
+                "bool" => self.add_typed_uniform::<bool>(logger, &uniform, false),
+                "int" => self.add_typed_uniform::<i32>(logger, &uniform, 0),
+                "uint" => self.add_typed_uniform::<u32>(logger, &uniform, 0),
+                "float" => self.add_typed_uniform::<f32>(logger, &uniform, 0.0),
+                "double" => self.add_typed_uniform::<f64>(logger, &uniform, 0.0),
 
                 // Vectors
-                "bvec2" => {
-                    self.add_uniform::<[bool; 2]>(logger, &uniform.name, "bvec2", [false; 2])
+                "bvec2" => self.add_typed_uniform::<[bool; 2]>(logger, &uniform, [false; 2]),
+                "bvec3" => self.add_typed_uniform::<[bool; 3]>(logger, &uniform, [false; 3]),
+                "bvec4" => self.add_typed_uniform::<[bool; 4]>(logger, &uniform, [false; 4]),
+                "ivec2" => self.add_typed_uniform::<[i32; 2]>(logger, &uniform, [0; 2]),
+                "ivec3" => self.add_typed_uniform::<[i32; 3]>(logger, &uniform, [0; 3]),
+                "ivec4" => self.add_typed_uniform::<[i32; 4]>(logger, &uniform, [0; 4]),
+                "uvec2" => self.add_typed_uniform::<[u32; 2]>(logger, &uniform, [0; 2]),
+                "uvec3" => self.add_typed_uniform::<[u32; 3]>(logger, &uniform, [0; 3]),
+                "uvec4" => self.add_typed_uniform::<[u32; 4]>(logger, &uniform, [0; 4]),
+                "vec2" => self.add_typed_uniform::<[f32; 2]>(logger, &uniform, [0.0; 2]),
+                "vec3" => self.add_typed_uniform::<[f32; 3]>(logger, &uniform, [0.0; 3]),
+                "vec4" => self.add_typed_uniform::<[f32; 4]>(logger, &uniform, [0.0; 4]),
+                "dvec2" => self.add_typed_uniform::<[f64; 2]>(logger, &uniform, [0.0; 2]),
+                "dvec3" => self.add_typed_uniform::<[f64; 3]>(logger, &uniform, [0.0; 3]),
+                "dvec4" => self.add_typed_uniform::<[f64; 4]>(logger, &uniform, [0.0; 4]),
+
+                // Matrices, as columns: matCxR is [[T; R]; C]
+                "mat2" => self.add_typed_uniform::<[[f32; 2]; 2]>(logger, &uniform, [[0.0; 2]; 2]),
+                "mat3" => self.add_typed_uniform::<[[f32; 3]; 3]>(logger, &uniform, [[0.0; 3]; 3]),
+                "mat4" => self.add_typed_uniform::<[[f32; 4]; 4]>(logger, &uniform, [[0.0; 4]; 4]),
+                "mat2x3" => {
+                    self.add_typed_uniform::<[[f32; 3]; 2]>(logger, &uniform, [[0.0; 3]; 2])
+                }
+                "mat2x4" => {
+                    self.add_typed_uniform::<[[f32; 4]; 2]>(logger, &uniform, [[0.0; 4]; 2])
+                }
+                "mat3x2" => {
+                    self.add_typed_uniform::<[[f32; 2]; 3]>(logger, &uniform, [[0.0; 2]; 3])
+                }
+                "mat3x4" => {
+                    self.add_typed_uniform::<[[f32; 4]; 3]>(logger, &uniform, [[0.0; 4]; 3])
+                }
+                "mat4x2" => {
+                    self.add_typed_uniform::<[[f32; 2]; 4]>(logger, &uniform, [[0.0; 2]; 4])
+                }
+                "mat4x3" => {
+                    self.add_typed_uniform::<[[f32; 3]; 4]>(logger, &uniform, [[0.0; 3]; 4])
                 }
-                "bvec3" => {
-                    self.add_uniform::<[bool; 3]>(logger, &uniform.name, "bvec3", [false; 3])
+                "dmat2" => self.add_typed_uniform::<[[f64; 2]; 2]>(logger, &uniform, [[0.0; 2]; 2]),
+                "dmat3" => self.add_typed_uniform::<[[f64; 3]; 3]>(logger, &uniform, [[0.0; 3]; 3]),
+                "dmat4" => self.add_typed_uniform::<[[f64; 4]; 4]>(logger, &uniform, [[0.0; 4]; 4]),
+                "dmat2x3" => {
+                    self.add_typed_uniform::<[[f64; 3]; 2]>(logger, &uniform, [[0.0; 3]; 2])
                 }
-                "bvec4" => {
-                    self.add_uniform::<[bool; 4]>(logger, &uniform.name, "bvec4", [false; 4])
+                "dmat2x4" => {
+                    self.add_typed_uniform::<[[f64; 4]; 2]>(logger, &uniform, [[0.0; 4]; 2])
                 }
-                "ivec2" => self.add_uniform::<[i32; 2]>(logger, &uniform.name, "ivec2", [0; 2]),
-                "ivec3" => self.add_uniform::<[i32; 3]>(logger, &uniform.name, "ivec3", [0; 3]),
-                "ivec4" => self.add_uniform::<[i32; 4]>(logger, &uniform.name, "ivec4", [0; 4]),
-                "uvec2" => self.add_uniform::<[u32; 2]>(logger, &uniform.name, "uvec2", [0; 2]),
-                "uvec3" => self.add_uniform::<[u32; 3]>(logger, &uniform.name, "uvec3", [0; 3]),
-                "uvec4" => self.add_uniform::<[u32; 4]>(logger, &uniform.name, "uvec4", [0; 4]),
-                "vec2" => self.add_uniform::<[f32; 2]>(logger, &uniform.name, "vec2", [0.0; 2]),
-                "vec3" => self.add_uniform::<[f32; 3]>(logger, &uniform.name, "vec3", [0.0; 3]),
-                "vec4" => self.add_uniform::<[f32; 4]>(logger, &uniform.name, "vec4", [0.0; 4]),
-                "dvec2" => self.add_uniform::<[f64; 2]>(logger, &uniform.name, "dvec2", [0.0; 2]),
-                "dvec3" => self.add_uniform::<[f64; 3]>(logger, &uniform.name, "dvec3", [0.0; 3]),
-                "dvec4" => self.add_uniform::<[f64; 4]>(logger, &uniform.name, "dvec4", [0.0; 4]),
-
-                // Images and samplers
-                "image1D" => self.add_image(logger, &uniform.name),
-                "image2D" => self.add_image(logger, &uniform.name),
-                "image3D" => self.add_image(logger, &uniform.name),
-                "sampler1D" => self.add_image(logger, &uniform.name),
-                "sampler2D" => self.add_image(logger, &uniform.name),
-                "sampler3D" => self.add_image(logger, &uniform.name),
-                _ => panic!("Unknown uniform type: {}", uniform.ty),
+                "dmat3x2" => {
+                    self.add_typed_uniform::<[[f64; 2]; 3]>(logger, &uniform, [[0.0; 2]; 3])
+                }
+                "dmat3x4" => {
+                    self.add_typed_uniform::<[[f64; 4]; 3]>(logger, &uniform, [[0.0; 4]; 3])
+                }
+                "dmat4x2" => {
+                    self.add_typed_uniform::<[[f64; 2]; 4]>(logger, &uniform, [[0.0; 2]; 4])
+                }
+                "dmat4x3" => {
+                    self.add_typed_uniform::<[[f64; 3]; 4]>(logger, &uniform, [[0.0; 3]; 4])
+                }
+
+                // Structs registered in the options
+                ty if self.options.uniform_structs.contains_key(ty) => {
+                    let create = self.options.uniform_structs[ty];
+                    self.insert_uniform(logger, &uniform.name, create(&uniform))
+                }
+
+                // Images and samplers, of every dimension and data type
+                ty if is_opaque_type(ty) && uniform.array_size.is_none() => {
+                    self.add_image(logger, &uniform.name)
+                }
+
+                // Structs and the types the engine can't upload yet are left to the user
+                ty => logger.info(
+                    "Uniform ".cyan()
+                        + quote!(uniform.name).magenta()
+                        + " has an unsupported type ".red()
+                        + ty.yellow(),
+                ),
+            }
+        }
+
+        self.log_blocks(logger);
+
+        logger.close_scope();
+    }
+
+    /// Logs the differences between the declared uniforms and the ones the program really has
+    fn cross_check_uniforms(
+        &self,
+        logger: &mut HTMLLogger,
+        declared: &[ProcessedUniform],
+        active: &[ProcessedUniform],
+    ) {
+        logger.open_scope("Introspection ".yellow());
+
+        // Members of struct uniforms are reported one by one, like `light.color`
+        let matches = |declared: &ProcessedUniform, active: &ProcessedUniform| {
+            active.name == declared.name
+                || active.name.starts_with(&format!("{}.", declared.name))
+                || active.name.starts_with(&format!("{}[", declared.name))
+        };
+
+        for uniform in declared.iter() {
+            if !active.iter().any(|active| matches(uniform, active)) {
+                logger.info(
+                    "Uniform ".cyan()
+                        + quote!(uniform.name).magenta()
+                        + " was optimised away".yellow(),
+                );
+            }
+        }
+        for uniform in active.iter() {
+            if !declared.iter().any(|declared| matches(declared, uniform)) {
+                logger.info(
+                    "Uniform ".cyan()
+                        + quote!(uniform.name).magenta()
+                        + " is not declared in the sources".yellow(),
+                );
             }
         }
 
         logger.close_scope();
     }
 
+    /// Logs the blocks of the program, buffers are bound to them by name
+    fn log_blocks(&self, logger: &mut HTMLLogger) {
+        let blocks = self
+            .interface
+            .uniform_blocks
+            .iter()
+            .map(|block| ("Uniform block ", block))
+            .chain(
+                self.interface
+                    .storage_blocks
+                    .iter()
+                    .map(|block| ("Storage block ", block)),
+            );
+        for (kind, block) in blocks {
+            logger.info(
+                kind.cyan()
+                    + quote!(block.name).magenta()
+                    + format!(
+                        ": binding {}, {} bytes, {} members",
+                        block.binding,
+                        block.data_size,
+                        block.members.len()
+                    )
+                    .green(),
+            );
+            if let Some(declared) = block.moved_from {
+                logger.info(
+                    kind.cyan()
+                        + quote!(block.name).magenta()
+                        + format!(
+                            " shared binding {} and was moved to {}, give it a `layout(binding = N)`",
+                            declared, block.binding
+                        )
+                        .yellow(),
+                );
+            }
+        }
+    }
+
+    /// Adds the uniform as `T`, or as `Vec<T>` with one element per entry if it's an array
+    fn add_typed_uniform<T: Clone + 'static>(
+        &mut self,
+        logger: &mut HTMLLogger,
+        uniform: &ProcessedUniform,
+        initial: T,
+    ) where
+        UniformVariable<T>: Uniform,
+        UniformVariable<Vec<T>>: Uniform,
+    {
+        match uniform.array_size {
+            Some(size) => {
+                let ty = format!("{}[{}]", uniform.ty, size);
+                self.add_uniform(logger, &uniform.name, &ty, vec![initial; size])
+            }
+            None => self.add_uniform(logger, &uniform.name, &uniform.ty, initial),
+        }
+    }
+
     fn add_uniform<T: 'static>(&mut self, logger: &mut HTMLLogger, name: &str, ty: &str, initial: T)
     where
         UniformVariable<T>: Uniform,
     {
         let uniform = UniformVariable::new(name, ty, initial);
-        let ref_uniform = Rc::new(RefCell::new(uniform));
+        self.insert_uniform(logger, name, Rc::new(RefCell::new(uniform)));
+    }
+
+    fn insert_uniform(
+        &mut self,
+        logger: &mut HTMLLogger,
+        name: &str,
+        ref_uniform: Rc<RefCell<dyn Uniform>>,
+    ) {
         let successful = ref_uniform.borrow_mut().bind_program(self.id);
-        self.uniforms.insert(name.to_string(), ref_uniform.clone());
+        self.uniforms.insert(name.to_string(), ref_uniform);
         match successful {
             Ok(_) => {
                 logger.info("Uniform ".cyan() + quote!(name).magenta() + " found".green());
@@ -338,6 +684,11 @@ impl<ST: ShaderType> ShaderProgram<ST> {
         let uniform_name = CString::new(name).unwrap();
         let location = unsafe { gl::GetUniformLocation(self.id, uniform_name.as_ptr()) };
         self.images.insert(name.to_string(), location);
+        if location == -1 {
+            logger.info("Image ".cyan() + quote!(name).magenta() + " not found".red());
+        } else {
+            logger.info("Image ".cyan() + quote!(name).magenta() + " found".green());
+        }
     }
 
     pub fn get_image_location(&self, name: &str) -> Option<GLint> {
@@ -345,6 +696,47 @@ impl<ST: ShaderType> ShaderProgram<ST> {
     }
 }
 
+// Buffer related functions
+impl<ST: ShaderType> ShaderProgram<ST> {
+    /// Binds the buffer to the binding point of the uniform block,
+    /// fails if the block doesn't exist or its layout doesn't match `T`.
+    /// Blocks without `layout(binding = N)` are given their own binding point when the program is linked
+    pub fn bind_uniform_buffer<T: BlockLayout>(
+        &self,
+        name: &str,
+        buffer: &mut UniformBuffer<T>,
+    ) -> Result<(), String> {
+        let block = self
+            .interface
+            .uniform_block(name)
+            .ok_or_else(|| format!("Uniform block \"{}\" not found in {}", name, self.name))?;
+        validate_block::<T>(block, LayoutRules::Std140)?;
+
+        buffer.bind(block.binding);
+        Ok(())
+    }
+}
+
+impl ShaderProgram<ComputeShader> {
+    /// Binds the buffer to the binding point of the storage block,
+    /// fails if the block doesn't exist or its array elements don't match `T`.
+    /// Like uniform blocks, storage blocks without a binding get their own one
+    pub fn bind_storage_buffer<T: BlockLayout>(
+        &self,
+        name: &str,
+        buffer: &StorageBuffer<T>,
+    ) -> Result<(), String> {
+        let block = self
+            .interface
+            .storage_block(name)
+            .ok_or_else(|| format!("Storage block \"{}\" not found in {}", name, self.name))?;
+        validate_storage_block::<T>(block)?;
+
+        buffer.bind(block.binding);
+        Ok(())
+    }
+}
+
 macro_rules! generate_shader_reload_functions {
     ($shader_type:ty, [$($field:ident),+]) => {
         #[allow(dead_code)]
@@ -365,12 +757,40 @@ macro_rules! generate_shader_reload_functions {
                 }
 
                 if any_updated {
-                    let main_scope = logger.open_scope("Reloading ".yellow() + self.name.magenta());
-                    let shader_result =
-                        ShaderProgram::<$shader_type>::new(logger, &self.name, $(&self.type_data.$field),+);
-                    self.try_reload(logger, shader_result, main_scope);
+                    self.reload(logger);
                 }
             }
+
+            /// Rebuilds the program from its files, keeping the old one if anything fails
+            pub fn reload(&mut self, logger: &mut HTMLLogger) {
+                self.rebuild(logger, self.options.clone());
+            }
+
+            /// Changes a define and recompiles the program with it, the define is kept only if the build succeeds
+            pub fn set_define<T: Display>(&mut self, logger: &mut HTMLLogger, name: &str, value: T) {
+                let mut options = self.options.clone();
+                options.set_define(name, value);
+                self.rebuild(logger, options);
+            }
+
+            /// Removes a define and recompiles the program without it, the define stays if the build fails
+            pub fn remove_define(&mut self, logger: &mut HTMLLogger, name: &str) {
+                let mut options = self.options.clone();
+                options.remove_define(name);
+                self.rebuild(logger, options);
+            }
+
+            /// Builds the program again with the options, which replace the current ones on success
+            fn rebuild(&mut self, logger: &mut HTMLLogger, options: ShaderOptions) {
+                let main_scope = logger.open_scope("Reloading ".yellow() + self.name.magenta());
+                let shader_result = ShaderProgram::<$shader_type>::new(
+                    logger,
+                    &self.name,
+                    $(&self.type_data.$field,)+
+                    options,
+                );
+                self.try_reload(logger, shader_result, main_scope);
+            }
         }
     };
 }
@@ -383,7 +803,7 @@ impl<ST: ShaderType> ShaderProgram<ST> {
     fn try_reload(
         &mut self,
         logger: &mut HTMLLogger,
-        new_shader: Result<ShaderProgram<ST>, String>,
+        new_shader: Result<ShaderProgram<ST>, ShaderError>,
         main_scope: Rc<RefCell<Details>>,
     ) {
         match new_shader {
@@ -400,6 +820,8 @@ impl<ST: ShaderType> ShaderProgram<ST> {
                     if let Some(new_uniform) = new_shader_program.uniforms.get(name) {
                         let new_uniform_str = new_uniform.borrow().to_string();
                         if new_uniform_str == uniform_str {
+                            // The locations can move around between programs
+                            let _ = uniform.borrow_mut().bind_program(new_shader_program.id);
                             new_shader_program
                                 .uniforms
                                 .insert(name.to_string(), uniform.clone());
@@ -418,12 +840,22 @@ impl<ST: ShaderType> ShaderProgram<ST> {
                     id,
                     shaders,
                     uniforms,
+                    link_diagnostics,
+                    interface,
+                    type_data,
+                    options,
                     ..
                 } = new_shader_program;
                 self.id = id;
+                self.type_data = type_data;
+                self.options = options;
                 self.using = false;
                 self.shaders = shaders;
                 self.uniforms = uniforms;
+                self.link_diagnostics = link_diagnostics;
+                self.interface = interface;
+                self.last_error = None;
+                self.update_record();
 
                 self.force_set_use();
                 self.handle_uniforms(true);
@@ -435,7 +867,8 @@ impl<ST: ShaderType> ShaderProgram<ST> {
                     .text
                     .push_str(" Success".green().as_str());
             }
-            Err(_) => {
+            Err(e) => {
+                self.last_error = Some(e);
                 main_scope
                     .borrow_mut()
                     .summary
@@ -449,50 +882,70 @@ impl<ST: ShaderType> ShaderProgram<ST> {
     }
 }
 
-/// Checks if a program has compiled successfully or not
+/// Work group size of a linked compute program
+fn query_local_size(program: GLuint) -> [u32; 3] {
+    let mut size = [0; 3];
+    unsafe {
+        gl::GetProgramiv(program, gl::COMPUTE_WORK_GROUP_SIZE, size.as_mut_ptr());
+    }
+    size.map(|v| v as u32)
+}
+
+/// Blocks any stage of a program declares with `layout(binding = N)`
+fn explicit_bindings(shaders: &[&Shader]) -> HashSet<String> {
+    shaders
+        .iter()
+        .flat_map(|shader| shader.data.explicit_bindings.iter().cloned())
+        .collect()
+}
+
+/// Images and samplers are bound to units instead of being uploaded like the other uniforms
+fn is_opaque_type(ty: &str) -> bool {
+    // Integer variants are prefixed, like `iimage2D` or `usampler3D`
+    ["", "i", "u"].iter().any(|prefix| {
+        ty.strip_prefix(prefix)
+            .is_some_and(|ty| ty.starts_with("image") || ty.starts_with("sampler"))
+    })
+}
+
+/// Checks if a program has linked successfully or not, the info log is read either way
 fn check_program(
     logger: &mut HTMLLogger,
     summary: &mut Summary,
     program: GLuint,
-) -> Result<(), String> {
-    // Success flag determines if the program compiled successfully
+    shaders: &[&Shader],
+    options: &ShaderOptions,
+) -> Result<Vec<Diagnostic>, ShaderError> {
+    // Success flag determines if the program linked successfully
     let mut success: GLint = 0;
     unsafe {
         gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
     }
 
-    // If the shader failed to compile, success will be 0
-    if success == 0 {
-        // Get the length of the error message using `gl::GetProgramiv`
-        let mut len: GLint = 0;
-        unsafe {
-            gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
-        }
-
-        // Create a buffer out of the length of the error message, gl functions require null terminated strings
-        // This is why we create a CString from the buffer
-        let mut buffer: Vec<u8> = Vec::with_capacity(len as usize + 1);
-        buffer.extend([b' '].iter().cycle().take(len as usize));
-        let error_message = unsafe { CString::from_vec_unchecked(buffer) };
-
-        unsafe {
-            gl::GetProgramInfoLog(
-                program,
-                len,
-                ptr::null_mut(),
-                error_message.as_ptr() as *mut GLchar,
-            );
-        }
-
-        logger.close_scope();
+    // Link messages are tied back to the attached stages and their files
+    let stages = shaders
+        .iter()
+        .map(|shader| LinkedStage {
+            name: stage_name(shader.shader_type),
+            data: &shader.data,
+        })
+        .collect::<Vec<_>>();
+    let info_log = read_info_log(program, gl::GetProgramiv, gl::GetProgramInfoLog);
+    let diagnostics = parse_link_log(info_log.as_str(), &options.log_parsers, &stages);
+
+    let data = shaders
+        .iter()
+        .map(|shader| &shader.data)
+        .collect::<Vec<_>>();
+    log_diagnostics(logger, "Link", &diagnostics, &data);
+    logger.close_scope();
 
+    // If the program failed to link, success will be 0
+    if success == 0 || (options.warnings_as_errors && has_warnings(&diagnostics)) {
         summary.text.push_str(" Failed".red().as_str());
-
-        let error_message = error_message.to_string_lossy().into_owned();
-        return Err(error_message);
+        return Err(ShaderError::Link(diagnostics));
     }
 
     summary.text.push_str(" Success".green().as_str());
-    logger.close_scope();
-    Ok(())
+    Ok(diagnostics)
 }
@@ -0,0 +1,168 @@
+use crate::shader::barrier::MemoryBarrier;
+use crate::shader::log_parser::{default_log_parsers, LogParser};
+use crate::shader::preprocessor::ProcessedUniform;
+use crate::shader::source::{DiskSource, ShaderSource};
+use crate::shader::uniform::{create_struct_uniform, Uniform, UniformStruct};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Display};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Root used when no root is given to a DiskSource, relative to the working directory
+pub const DEFAULT_SHADER_ROOT: &str = "res/shaders";
+
+/// Where a program finds the uniforms it creates UniformVariables for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UniformDiscovery {
+    /// Uniforms declared in the sources, found by the preprocessor
+    #[default]
+    Preprocessor,
+    /// Active uniforms of the linked program, anything the compiler optimised away is skipped.
+    /// The result is cross-checked against the declarations and the differences are logged
+    Introspection,
+}
+
+/// Creates the uniform for a declaration of a registered struct type
+pub(crate) type StructUniformFactory = fn(&ProcessedUniform) -> Rc<RefCell<dyn Uniform>>;
+
+/// Settings used while building a ShaderProgram.
+/// The program keeps its own copy, so hot reloads are built with the same settings
+#[derive(Clone, Debug)]
+pub struct ShaderOptions {
+    pub(crate) defines: BTreeMap<String, String>,
+    pub(crate) roots: Vec<PathBuf>,
+    pub(crate) libraries: Vec<PathBuf>,
+    pub(crate) source: Rc<dyn ShaderSource>,
+    pub(crate) line_directives: bool,
+    pub(crate) log_parsers: Vec<Rc<dyn LogParser>>,
+    pub(crate) warnings_as_errors: bool,
+    pub(crate) uniform_discovery: UniformDiscovery,
+    pub(crate) uniform_structs: HashMap<String, StructUniformFactory>,
+    pub(crate) barrier: Option<MemoryBarrier>,
+}
+
+impl Default for ShaderOptions {
+    fn default() -> Self {
+        ShaderOptions {
+            defines: BTreeMap::new(),
+            roots: Vec::new(),
+            libraries: Vec::new(),
+            source: Rc::new(DiskSource),
+            line_directives: false,
+            log_parsers: default_log_parsers(),
+            warnings_as_errors: false,
+            uniform_discovery: UniformDiscovery::default(),
+            uniform_structs: HashMap::new(),
+            barrier: None,
+        }
+    }
+}
+
+impl ShaderOptions {
+    pub fn new() -> ShaderOptions {
+        ShaderOptions::default()
+    }
+
+    /// Adds a define, it is inserted right after the `#version` line as `#define name value`
+    pub fn define<T: Display>(mut self, name: &str, value: T) -> ShaderOptions {
+        self.set_define(name, value);
+        self
+    }
+
+    pub fn set_define<T: Display>(&mut self, name: &str, value: T) {
+        self.defines.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove_define(&mut self, name: &str) {
+        self.defines.remove(name);
+    }
+
+    pub fn get_define(&self, name: &str) -> Option<&str> {
+        self.defines.get(name).map(String::as_str)
+    }
+
+    /// Adds a shader root, program files and `"..."` includes that aren't next to
+    /// the including file are searched in the roots, in the order they were added
+    pub fn root<P: AsRef<Path>>(mut self, path: P) -> ShaderOptions {
+        self.add_root(path);
+        self
+    }
+
+    pub fn add_root<P: AsRef<Path>>(&mut self, path: P) {
+        self.roots.push(path.as_ref().to_path_buf());
+    }
+
+    /// Adds a library root, `<...>` includes are searched in the libraries first, then in the roots
+    pub fn library<P: AsRef<Path>>(mut self, path: P) -> ShaderOptions {
+        self.add_library(path);
+        self
+    }
+
+    pub fn add_library<P: AsRef<Path>>(&mut self, path: P) {
+        self.libraries.push(path.as_ref().to_path_buf());
+    }
+
+    /// Sets where the files are read from, the default is the DiskSource
+    pub fn source<S: ShaderSource + 'static>(self, source: S) -> ShaderOptions {
+        self.shared_source(Rc::new(source))
+    }
+
+    /// Same as `source`, but the caller can keep a handle, for example to update a MemorySource
+    pub fn shared_source(mut self, source: Rc<dyn ShaderSource>) -> ShaderOptions {
+        self.source = source;
+        self
+    }
+
+    /// Emits `#line line file_index` directives at include boundaries, so the line numbers in driver
+    /// messages point to the original files even for messages the engine can't parse
+    pub fn line_directives(mut self, enabled: bool) -> ShaderOptions {
+        self.line_directives = enabled;
+        self
+    }
+
+    /// Adds a parser for the info logs of a driver, it is tried before the built-in parsers
+    pub fn log_parser<P: LogParser + 'static>(mut self, parser: P) -> ShaderOptions {
+        self.log_parsers.insert(0, Rc::new(parser));
+        self
+    }
+
+    /// Makes compiler and linker warnings fail the build, like errors do
+    pub fn warnings_as_errors(mut self, enabled: bool) -> ShaderOptions {
+        self.warnings_as_errors = enabled;
+        self
+    }
+
+    /// Sets where the uniforms of the program come from, the default is the preprocessor
+    pub fn uniform_discovery(mut self, discovery: UniformDiscovery) -> ShaderOptions {
+        self.uniform_discovery = discovery;
+        self
+    }
+
+    /// Registers a struct, uniforms declared with its GLSL type become `UniformVariable<T>`,
+    /// or `UniformVariable<Vec<T>>` for arrays of it. Needs `UniformDiscovery::Preprocessor`,
+    /// introspection only reports the members one by one
+    pub fn uniform_struct<T: UniformStruct + Clone + Default + Debug + 'static>(
+        mut self,
+    ) -> ShaderOptions {
+        self.uniform_structs
+            .insert(T::GLSL_NAME.to_string(), create_struct_uniform::<T>);
+        self
+    }
+
+    /// Sets the barrier compute dispatches issue, instead of the one inferred from the images and
+    /// storage blocks of the program
+    pub fn barrier(mut self, barrier: MemoryBarrier) -> ShaderOptions {
+        self.barrier = Some(barrier);
+        self
+    }
+
+    /// The roots to search in, falls back to the roots of the source when none were added
+    pub(crate) fn get_roots(&self) -> Vec<PathBuf> {
+        if self.roots.is_empty() {
+            self.source.default_roots()
+        } else {
+            self.roots.clone()
+        }
+    }
+}
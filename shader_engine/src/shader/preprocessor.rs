@@ -1,16 +1,26 @@
 use crate::quote;
+use crate::shader::diagnostic::ShaderError;
+use crate::shader::expression::{evaluate, evaluate_constant};
+use crate::shader::options::ShaderOptions;
+use crate::shader::source::ShaderSource;
 use crate::utils::colorized_text::Colorize;
 use crate::utils::html_logger::HTMLLogger;
-use regex::{Captures, Regex};
-use std::fs::File;
-use std::io::Read;
-use std::path::{Path, PathBuf};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::{Component, Path, PathBuf};
 
-pub const SHADER_FILE_PREFIX: &str = "res/shaders/";
+/// Source name used for the defines injected from the ShaderOptions
+pub(crate) const DEFINES_SOURCE: &str = "<defines>";
 const IGNORE_START: &str = r"\/\*\s*<ignore>\s*\*\/";
 const IGNORE_END: &str = r"\/\*\s*<\/ignore>\s*\*\/";
-const INCLUDE_PATTERN: &str = r####"#include\s+\"(.+)\""####;
-const UNIFORM_PATTERN: &str = r"uniform\s+(.+)\s+(.+)\s*;";
+const DIRECTIVE_PATTERN: &str = r"^\s*#\s*(\w*)\s*(.*)$";
+const INCLUDE_PATTERN: &str = r####"^(?:\"(.+)\"|<(.+)>)"####;
+const DEFINE_PATTERN: &str = r"^([A-Za-z_]\w*)(\(([^)]*)\))?\s*(.*)$";
+const VERSION_PATTERN: &str = r"^(\d+)\s*(\w*)";
+/// Qualifiers that can come before the type of a uniform
+const PRECISION_QUALIFIERS: [&str; 3] = ["lowp", "mediump", "highp"];
+const MEMORY_QUALIFIERS: [&str; 5] = ["coherent", "volatile", "restrict", "readonly", "writeonly"];
 
 /// A struct to contain the necessary information
 /// through the shader transpilation process
@@ -18,45 +28,198 @@ const UNIFORM_PATTERN: &str = r"uniform\s+(.+)\s+(.+)\s*;";
 #[derive(Debug)]
 pub(crate) struct ProcessData {
     pub(crate) processed_source: String,
+    /// Every source that added lines to the output, the index of a file
+    /// is also its source string number in the emitted `#line` directives
     pub(crate) included_files: Vec<String>,
     pub(crate) line_to_source: Vec<(String, usize)>,
+    /// Contents of every included file, used to show the lines the diagnostics point to
+    pub(crate) sources: HashMap<String, String>,
+    /// Whether the output contains `#line` directives, so driver messages already point to the right file
+    pub(crate) line_directives: bool,
+    /// File index and line number the driver expects for the next output line
+    next_line: (usize, usize),
     pub(crate) uniforms: Vec<ProcessedUniform>,
+    /// Uniform and storage blocks declared with `layout(binding = N)`, the engine never moves them
+    pub(crate) explicit_bindings: HashSet<String>,
+    /// Tokens of the active code, uniforms are read from them once every file is processed
+    tokens: Vec<GlslToken>,
+    pub(crate) defines: HashMap<String, Macro>,
+    injected_defines: Vec<(String, String)>,
+    /// Files currently being processed, from the root file to the innermost include
+    include_stack: Vec<String>,
+    /// Files marked with `#pragma once`
+    once_files: HashSet<String>,
+    /// Files wrapped in a classic `#ifndef X / #define X / #endif` guard, with their guard macro
+    include_guards: HashMap<String, String>,
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct ProcessedUniform {
     pub(crate) name: String,
+    /// Base type, without the array size
     pub(crate) ty: String,
+    pub(crate) array_size: Option<usize>,
+    /// Entries of the `layout(...)` qualifier, like `binding = 0` or `rgba32f`
+    pub(crate) layout: Vec<(String, Option<String>)>,
+    /// Memory qualifiers, like `readonly` or `coherent`
+    pub(crate) memory: Vec<String>,
+    pub(crate) precision: Option<String>,
 }
 
-/// Reads a file and returns its contents as a string
-fn read_file(path: &str) -> Result<String, String> {
-    let full_path = Path::new(SHADER_FILE_PREFIX)
-        .join(path)
-        .canonicalize()
-        .unwrap();
-    let mut file = File::open(full_path).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    Ok(contents)
+/// A token of the active GLSL code, with the index of its file in `included_files` and its line
+#[derive(Debug)]
+struct GlslToken {
+    text: String,
+    file: usize,
+    line: usize,
+}
+
+/// A macro created by `#define`, `params` is only set for function like macros
+#[derive(Clone, Debug)]
+pub(crate) struct Macro {
+    pub(crate) params: Option<Vec<String>>,
+    pub(crate) body: String,
+}
+
+/// A single `#if`/`#ifdef`/`#ifndef` block, alive until its `#endif`
+struct Conditional {
+    /// Whether the lines of the current branch are part of the output
+    active: bool,
+    /// Whether one of the branches has already been taken
+    taken: bool,
+    /// Whether the block surrounding this one is active
+    parent_active: bool,
+    /// Set after `#else`, so a following `#elif` or `#else` can be rejected
+    else_seen: bool,
+    /// Line of the opening directive, used when the block is never closed
+    line: usize,
+}
+
+/// Tracks whether a whole file is wrapped in a classic include guard
+enum GuardState {
+    /// Nothing significant has been seen yet
+    Start,
+    /// The first directive was `#ifndef X`, waiting for `#define X`
+    Opened(String),
+    /// Inside the guard, waiting for the closing `#endif`
+    Defined(String),
+    /// The guard has been closed, nothing may follow it
+    Closed(String),
+    /// The file isn't guarded
+    Invalid,
+}
+
+/// Regex objects used while handling a file, compiled once per shader
+struct Patterns {
+    ignore_start: Regex,
+    ignore_end: Regex,
+    directive: Regex,
+    include: Regex,
+    define: Regex,
+    version: Regex,
+}
+
+impl Patterns {
+    fn new() -> Patterns {
+        Patterns {
+            ignore_start: Regex::new(IGNORE_START).unwrap(),
+            ignore_end: Regex::new(IGNORE_END).unwrap(),
+            directive: Regex::new(DIRECTIVE_PATTERN).unwrap(),
+            include: Regex::new(INCLUDE_PATTERN).unwrap(),
+            define: Regex::new(DEFINE_PATTERN).unwrap(),
+            version: Regex::new(VERSION_PATTERN).unwrap(),
+        }
+    }
+}
+
+/// Returns the path of the file in the first directory that contains it
+fn find_file(
+    source: &dyn ShaderSource,
+    file: &str,
+    directories: &[PathBuf],
+) -> Result<PathBuf, String> {
+    directories
+        .iter()
+        .map(|directory| directory.join(file))
+        .find(|path| source.exists(path))
+        .ok_or_else(|| {
+            let searched = directories
+                .iter()
+                .map(|directory| quote!(directory.display()))
+                .collect::<Vec<_>>();
+            format!("Cannot find {} in [{}]", quote!(file), searched.join(", "))
+        })
 }
 
 /// Processes a shader file and returns the necessary information
-pub fn process_shader(logger: &mut HTMLLogger, file_name: &str) -> Result<ProcessData, String> {
+pub fn process_shader(
+    logger: &mut HTMLLogger,
+    file_name: &str,
+    options: &ShaderOptions,
+) -> Result<ProcessData, ShaderError> {
     let mut data = ProcessData {
         processed_source: String::new(),
         included_files: Vec::new(),
         line_to_source: Vec::new(),
+        sources: HashMap::new(),
+        line_directives: options.line_directives,
+        next_line: (0, 1),
         uniforms: Vec::new(),
+        explicit_bindings: HashSet::new(),
+        tokens: Vec::new(),
+        defines: HashMap::new(),
+        injected_defines: Vec::new(),
+        include_stack: Vec::new(),
+        once_files: HashSet::new(),
+        include_guards: HashMap::new(),
     };
 
+    // Injected defines are visible to the conditionals from the very first line
+    for (name, value) in options.defines.iter() {
+        data.defines.insert(name.clone(), Macro::object(value));
+        data.injected_defines.push((name.clone(), value.clone()));
+    }
+
+    let patterns = Patterns::new();
+
     logger.open_scope("Processing ".yellow());
-    match handle_file(logger, Path::new(file_name).to_path_buf(), &mut data) {
+    if !data.injected_defines.is_empty() {
+        logger.open_scope("Defines".yellow());
+        for (name, value) in data.injected_defines.iter() {
+            logger.info(name.magenta() + " = ".cyan() + value.green());
+        }
+        logger.close_scope();
+    }
+
+    let file_path = find_file(options.source.as_ref(), file_name, &options.get_roots())
+        .map_err(|e| ShaderError::io(None, None, e))?;
+    match handle_file(logger, &patterns, options, file_path, &mut data) {
         Ok(_) => {
+            (data.uniforms, data.explicit_bindings) = parse_uniforms(&data)?;
+
+            // Shaders without a `#version` line get the defines at the very top
+            if !data.injected_defines.is_empty() {
+                let source = std::mem::take(&mut data.processed_source);
+                let mut line_to_source = std::mem::take(&mut data.line_to_source);
+                inject_defines(&mut data);
+
+                // The root file continues from its first line after the defines
+                if data.line_directives {
+                    let root = data.included_files[0].clone();
+                    data.line_to_source.push((root, 1));
+                    data.processed_source.push_str("#line 1 0\n");
+                }
+
+                data.processed_source.push_str(&source);
+                data.line_to_source.append(&mut line_to_source);
+            }
+
             if !data.uniforms.is_empty() {
                 logger.open_scope("Uniforms".yellow());
                 for uniform in data.uniforms.iter() {
-                    logger.info(quote!(uniform.name).magenta() + ": ".cyan() + uniform.ty.green());
+                    logger.info(
+                        quote!(uniform.name).magenta() + ": ".cyan() + uniform.to_string().green(),
+                    );
                 }
                 logger.close_scope();
             } else {
@@ -74,91 +237,1221 @@ pub fn process_shader(logger: &mut HTMLLogger, file_name: &str) -> Result<Proces
 
 fn handle_file(
     logger: &mut HTMLLogger,
+    patterns: &Patterns,
+    options: &ShaderOptions,
     file_path: PathBuf,
     data: &mut ProcessData,
-) -> Result<(), String> {
+) -> Result<(), ShaderError> {
+    let file_path = normalize_path(&file_path);
     let file_name = file_path.to_str().unwrap();
-    logger.info("Including ".cyan() + quote!(file_name).magenta());
 
-    // Check if the file has already been included
-    if data.included_files.contains(&file_name.to_string()) {
-        return Err(format!("\"{}\" included multiple times", file_name));
+    // A file including itself is a cycle even when it is protected, the protection would only hide it
+    if data
+        .include_stack
+        .iter()
+        .any(|included| included == file_name)
+    {
+        let chain = data
+            .include_stack
+            .iter()
+            .map(String::as_str)
+            .chain([file_name])
+            .collect::<Vec<_>>();
+        let message = format!("Include cycle: {}", chain.join(" → "));
+        let including_file = data.include_stack.last().map(String::as_str);
+        return Err(ShaderError::preprocess(including_file, None, message));
+    }
+
+    // Files protected by `#pragma once` or an include guard are only included once
+    let guard_defined = data
+        .include_guards
+        .get(file_name)
+        .is_some_and(|guard| data.defines.contains_key(guard));
+    if data.once_files.contains(file_name) || guard_defined {
+        logger.info(
+            "Skipping ".cyan() + quote!(file_name).magenta() + " (already included)".yellow(),
+        );
+        return Ok(());
+    }
+
+    logger.info("Including ".cyan() + quote!(file_name).magenta());
+    if !data
+        .included_files
+        .iter()
+        .any(|included| included == file_name)
+    {
+        data.included_files.push(file_name.to_string());
     }
-    data.included_files.push(file_name.to_string());
 
-    let file_contents = read_file(file_name)?;
+    data.include_stack.push(file_name.to_string());
+    let result = process_lines(logger, patterns, options, &file_path, data);
+    data.include_stack.pop();
+    result
+}
+
+/// Goes through the lines of a file, handling the directives and emitting the active lines
+fn process_lines(
+    logger: &mut HTMLLogger,
+    patterns: &Patterns,
+    options: &ShaderOptions,
+    file_path: &Path,
+    data: &mut ProcessData,
+) -> Result<(), ShaderError> {
+    let file_name = file_path.to_str().unwrap();
+    let file_contents = options
+        .source
+        .read(file_path)
+        .map_err(|e| ShaderError::io(Some(file_name), None, e))?;
+    data.sources
+        .insert(file_name.to_string(), file_contents.clone());
+    let lines = file_contents.lines().collect::<Vec<_>>();
     let mut ignore = false;
+    let mut in_comment = false;
+    let mut conditionals: Vec<Conditional> = Vec::new();
+    let mut guard = GuardState::Start;
 
-    // Turn the regex strings into regex objects that rust can use
-    let ignore_start = Regex::new(IGNORE_START).unwrap();
-    let ignore_end = Regex::new(IGNORE_END).unwrap();
-    let include_pattern = Regex::new(INCLUDE_PATTERN).unwrap();
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        let line_number = index + 1;
+        index += 1;
 
-    let file_base = file_path.parent().unwrap().to_path_buf();
-    for (line_number, line) in file_contents.lines().enumerate() {
         // The first thing we check is the ignore flag
-        if ignore_start.is_match(line) {
+        if patterns.ignore_start.is_match(line) {
             ignore = true;
         }
 
-        if ignore_end.is_match(line) {
+        if patterns.ignore_end.is_match(line) {
             ignore = false;
         }
 
-        // Then we check for the imports
-        if let Some(capture) = include_pattern.captures(line) {
-            if ignore {
-                continue;
+        let active = conditionals.last().is_none_or(|c| c.active);
+        let mut code = strip_comments(line, &mut in_comment);
+        if !patterns.directive.is_match(&code) {
+            // Any code outside the guard means the file isn't guarded
+            if !code.trim().is_empty() && !matches!(guard, GuardState::Defined(_)) {
+                guard = GuardState::Invalid;
             }
 
-            include_capture(logger, line, &capture, &file_base, data)?;
+            if active {
+                emit_line(line, &code, file_name, line_number, data);
+            }
             continue;
         }
 
-        // First things first, we add the line to the line_to_source map
-        data.line_to_source
-            .push((file_name.to_string(), line_number + 1));
+        // Directives can continue on the next line with a trailing backslash
+        let mut physical_lines = vec![line];
+        while code.ends_with('\\') && index < lines.len() {
+            code.pop();
+            code.push_str(&strip_comments(lines[index], &mut in_comment));
+            physical_lines.push(lines[index]);
+            index += 1;
+        }
+        let capture = patterns.directive.captures(&code).unwrap();
+
+        let directive = capture.get(1).unwrap().as_str();
+        let argument = capture.get(2).unwrap().as_str().trim();
+        let error = |message| ShaderError::preprocess(Some(file_name), Some(line_number), message);
+
+        guard = match (guard, directive) {
+            (GuardState::Start, "ifndef") => GuardState::Opened(macro_name(argument).to_string()),
+            (GuardState::Opened(name), "define") if macro_name(argument) == name => {
+                GuardState::Defined(name)
+            }
+            (GuardState::Defined(name), "endif") if conditionals.len() == 1 => {
+                GuardState::Closed(name)
+            }
+            (GuardState::Defined(_), "elif" | "else") if conditionals.len() == 1 => {
+                GuardState::Invalid
+            }
+            (GuardState::Defined(name), _) => GuardState::Defined(name),
+            _ => GuardState::Invalid,
+        };
+
+        match directive {
+            "if" | "ifdef" | "ifndef" => {
+                let condition = active
+                    && match directive {
+                        "if" => evaluate(argument, &data.defines).map_err(error)? != 0,
+                        "ifdef" => data.defines.contains_key(macro_name(argument)),
+                        _ => !data.defines.contains_key(macro_name(argument)),
+                    };
+
+                conditionals.push(Conditional {
+                    active: condition,
+                    taken: condition,
+                    parent_active: active,
+                    else_seen: false,
+                    line: line_number,
+                });
+            }
+            "elif" => {
+                let Some(conditional) = conditionals.last_mut() else {
+                    return Err(error("#elif without #if".to_string()));
+                };
+                if conditional.else_seen {
+                    return Err(error("#elif after #else".to_string()));
+                }
+
+                // The expression is only evaluated when no previous branch was taken
+                conditional.active = conditional.parent_active
+                    && !conditional.taken
+                    && evaluate(argument, &data.defines).map_err(error)? != 0;
+                conditional.taken |= conditional.active;
+            }
+            "else" => {
+                let Some(conditional) = conditionals.last_mut() else {
+                    return Err(error("#else without #if".to_string()));
+                };
+                if conditional.else_seen {
+                    return Err(error("#else after #else".to_string()));
+                }
+
+                conditional.else_seen = true;
+                conditional.active = conditional.parent_active && !conditional.taken;
+                conditional.taken = true;
+            }
+            "endif" => {
+                if conditionals.pop().is_none() {
+                    return Err(error("#endif without #if".to_string()));
+                }
+            }
+            _ if !active => {}
+            "include" => {
+                if ignore {
+                    continue;
+                }
+
+                let Some(include) = patterns.include.captures(argument) else {
+                    return Err(error(format!("Invalid #include {}", argument)));
+                };
+                // "..." starts next to the including file, <...> starts in the libraries
+                let mut directories = Vec::new();
+                let include_file = match include.get(1) {
+                    Some(quoted) => {
+                        directories.push(file_path.parent().unwrap().to_path_buf());
+                        directories.extend(options.get_roots());
+                        directories.extend(options.libraries.iter().cloned());
+                        quoted.as_str()
+                    }
+                    None => {
+                        directories.extend(options.libraries.iter().cloned());
+                        directories.extend(options.get_roots());
+                        include.get(2).unwrap().as_str()
+                    }
+                };
+
+                let include_path =
+                    find_file(options.source.as_ref(), include_file, &directories)
+                        .map_err(|e| ShaderError::io(Some(file_name), Some(line_number), e))?;
+                handle_file(logger, patterns, options, include_path, data)?;
+            }
+            "define" => {
+                let Some(define) = patterns.define.captures(argument) else {
+                    return Err(error(format!("Invalid #define {}", argument)));
+                };
 
-        // If it isn't an import, we can add it to the transpiled source
-        data.processed_source.push_str(line);
-        data.processed_source.push('\n');
+                let name = define.get(1).unwrap().as_str();
+                let params = define.get(3).map(|params| {
+                    params
+                        .as_str()
+                        .split(',')
+                        .map(|param| param.trim().to_string())
+                        .filter(|param| !param.is_empty())
+                        .collect()
+                });
+                let body = define.get(4).unwrap().as_str().trim().to_string();
+                data.defines
+                    .insert(name.to_string(), Macro { params, body });
 
-        // Then we check for the uniforms
-        uniform_capture(line, data);
+                emit_directive(&physical_lines, file_name, line_number, data);
+            }
+            "undef" => {
+                data.defines.remove(macro_name(argument));
+                emit_directive(&physical_lines, file_name, line_number, data);
+            }
+            "pragma" if argument == "once" => {
+                data.once_files.insert(file_name.to_string());
+            }
+            "version" => {
+                // Mirror the macros the driver defines for the version line
+                if let Some(version) = patterns.version.captures(argument) {
+                    let number = version.get(1).unwrap().as_str();
+                    let profile = version.get(2).unwrap().as_str();
+                    data.defines
+                        .insert("__VERSION__".to_string(), Macro::object(number));
+                    if profile != "compatibility" {
+                        data.defines
+                            .insert("GL_core_profile".to_string(), Macro::object("1"));
+                    }
+                }
+
+                emit_directive(&physical_lines, file_name, line_number, data);
+                inject_defines(data);
+            }
+            _ => emit_directive(&physical_lines, file_name, line_number, data),
+        }
+    }
+
+    if let Some(conditional) = conditionals.last() {
+        return Err(ShaderError::preprocess(
+            Some(file_name),
+            Some(conditional.line),
+            "Unterminated conditional directive".to_string(),
+        ));
+    }
+
+    if let GuardState::Closed(name) = guard {
+        data.include_guards.insert(file_name.to_string(), name);
     }
 
     Ok(())
 }
 
-fn include_capture(
-    logger: &mut HTMLLogger,
-    line: &str,
-    capture: &Captures,
-    file_base: &Path,
-    data: &mut ProcessData,
-) -> Result<(), String> {
-    if line.starts_with("//") {
-        return Ok(());
+/// Resolves the `.` and `..` parts of a path, so every file has a single name
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+impl Macro {
+    fn object(body: &str) -> Macro {
+        Macro {
+            params: None,
+            body: body.to_string(),
+        }
+    }
+}
+
+impl Display for ProcessedUniform {
+    /// Formats the uniform like its declaration, without the name
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if !self.layout.is_empty() {
+            let entries = self
+                .layout
+                .iter()
+                .map(|(name, value)| match value {
+                    Some(value) => format!("{} = {}", name, value),
+                    None => name.clone(),
+                })
+                .collect::<Vec<_>>();
+            write!(f, "layout({}) ", entries.join(", "))?;
+        }
+        for qualifier in self.memory.iter().chain(self.precision.iter()) {
+            write!(f, "{} ", qualifier)?;
+        }
+
+        write!(f, "{}", self.ty)?;
+        if let Some(size) = self.array_size {
+            write!(f, "[{}]", size)?;
+        }
+        Ok(())
+    }
+}
+
+impl ProcessData {
+    /// Maps a source string number and line reported by the driver back to the original file and line
+    pub(crate) fn resolve(&self, source: usize, line: usize) -> Option<(String, usize)> {
+        // Drivers use line 0 for messages about the whole shader
+        if line == 0 {
+            return None;
+        }
+
+        // With `#line` directives the driver already reports the original position
+        if self.line_directives {
+            return self
+                .included_files
+                .get(source)
+                .map(|file| (file.clone(), line));
+        }
+
+        self.line_to_source.get(line - 1).cloned()
     }
 
-    let include_file = capture.get(1).unwrap().as_str();
-    let new_file_path = file_base.join(include_file);
-    handle_file(logger, new_file_path, data)
+    /// Finds the first output line that uses the identifier, used for messages that only name something
+    pub(crate) fn find_identifier(&self, identifier: &str) -> Option<(String, usize)> {
+        let pattern = Regex::new(&format!(r"\b{}\b", regex::escape(identifier))).unwrap();
+        let index = self
+            .processed_source
+            .lines()
+            .position(|line| !line.starts_with("#line") && pattern.is_match(line))?;
+        self.line_to_source.get(index).cloned()
+    }
 }
 
-fn uniform_capture(line: &str, data: &mut ProcessData) {
-    if line.starts_with("//") {
+/// Emits the defines coming from the ShaderOptions, only the first call does anything
+fn inject_defines(data: &mut ProcessData) {
+    let injected_defines = std::mem::take(&mut data.injected_defines);
+    if injected_defines.is_empty() {
         return;
     }
 
-    let uniform_pattern = Regex::new(UNIFORM_PATTERN).unwrap();
-    let uniform_capture = uniform_pattern.captures(line);
-    if let Some(uniform_capture) = uniform_capture {
-        let uniform_type = uniform_capture.get(1).unwrap().as_str();
-        let uniform_name = uniform_capture.get(2).unwrap().as_str();
-        data.uniforms.push(ProcessedUniform {
-            name: uniform_name.to_string(),
-            ty: uniform_type.to_string(),
+    data.included_files.push(DEFINES_SOURCE.to_string());
+    let mut contents = String::new();
+    for (index, (name, value)) in injected_defines.into_iter().enumerate() {
+        let define = format!("#define {} {}", name, value);
+        push_line(data, DEFINES_SOURCE, index + 1, &define);
+        contents.push_str(&define);
+        contents.push('\n');
+    }
+    data.sources.insert(DEFINES_SOURCE.to_string(), contents);
+}
+
+/// Appends a line to the output, keeping track of where it came from.
+/// With line directives on, a `#line` is emitted whenever the output jumps to another place
+fn push_line(data: &mut ProcessData, file_name: &str, line_number: usize, line: &str) {
+    if data.line_directives {
+        let file_index = data
+            .included_files
+            .iter()
+            .position(|included| included == file_name)
+            .unwrap();
+
+        if data.next_line != (file_index, line_number) {
+            data.line_to_source
+                .push((file_name.to_string(), line_number));
+            data.processed_source
+                .push_str(&format!("#line {} {}\n", line_number, file_index));
+        }
+        data.next_line = (file_index, line_number + 1);
+    }
+
+    // First things first, we add the line to the line_to_source map
+    data.line_to_source
+        .push((file_name.to_string(), line_number));
+
+    // Then we can add it to the transpiled source
+    data.processed_source.push_str(line);
+    data.processed_source.push('\n');
+}
+
+/// Adds a single source line to the output and keeps the tokens of its code for the uniforms
+fn emit_line(line: &str, code: &str, file_name: &str, line_number: usize, data: &mut ProcessData) {
+    push_line(data, file_name, line_number, line);
+
+    let file_index = data
+        .included_files
+        .iter()
+        .position(|included| included == file_name)
+        .unwrap();
+    tokenize_glsl(code, file_index, line_number, &mut data.tokens);
+}
+
+/// Passes a directive through to the driver, including its continuation lines
+fn emit_directive(lines: &[&str], file_name: &str, line_number: usize, data: &mut ProcessData) {
+    for (offset, line) in lines.iter().enumerate() {
+        push_line(data, file_name, line_number + offset, line);
+    }
+}
+
+/// First word of a directive argument, as in `#ifdef NAME`
+fn macro_name(argument: &str) -> &str {
+    argument.split_whitespace().next().unwrap_or("")
+}
+
+/// Removes the comments from a line, `in_comment` carries unterminated block comments to the next line
+fn strip_comments(line: &str, in_comment: &mut bool) -> String {
+    let mut code = String::new();
+    let mut rest = line;
+
+    loop {
+        if *in_comment {
+            match rest.find("*/") {
+                Some(end) => {
+                    *in_comment = false;
+                    rest = &rest[end + 2..];
+                    code.push(' ');
+                }
+                None => return code,
+            }
+        }
+
+        let line_comment = rest.find("//");
+        let block_comment = rest.find("/*");
+        match (line_comment, block_comment) {
+            (Some(line_start), Some(block_start)) if line_start < block_start => {
+                code.push_str(&rest[..line_start]);
+                return code;
+            }
+            (Some(line_start), None) => {
+                code.push_str(&rest[..line_start]);
+                return code;
+            }
+            (_, Some(block_start)) => {
+                code.push_str(&rest[..block_start]);
+                rest = &rest[block_start + 2..];
+                *in_comment = true;
+            }
+            (None, None) => {
+                code.push_str(rest);
+                return code;
+            }
+        }
+    }
+}
+
+/// Splits GLSL code into identifiers, numbers and single character punctuations
+fn tokenize_glsl(code: &str, file: usize, line: usize, tokens: &mut Vec<GlslToken>) {
+    let chars = code.chars().collect::<Vec<_>>();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let char = chars[index];
+        if char.is_whitespace() {
+            index += 1;
+            continue;
+        }
+
+        let start = index;
+        if char.is_alphanumeric() || char == '_' || char == '.' {
+            while index < chars.len()
+                && (chars[index].is_alphanumeric() || chars[index] == '_' || chars[index] == '.')
+            {
+                index += 1;
+            }
+        } else {
+            index += 1;
+        }
+
+        tokens.push(GlslToken {
+            text: chars[start..index].iter().collect(),
+            file,
+            line,
         });
     }
 }
+
+/// Finds every uniform declaration outside of functions and blocks in the active code
+/// Reads the uniforms of the default block, and the blocks that set their own binding
+fn parse_uniforms(
+    data: &ProcessData,
+) -> Result<(Vec<ProcessedUniform>, HashSet<String>), ShaderError> {
+    let tokens = &data.tokens;
+    let mut uniforms = Vec::new();
+    let mut explicit_bindings = HashSet::new();
+    let mut depth = 0usize;
+    let mut statement_start = 0;
+
+    let mut index = 0;
+    while index < tokens.len() {
+        match tokens[index].text.as_str() {
+            "{" => depth += 1,
+            "}" => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    statement_start = index + 1;
+                }
+            }
+            ";" if depth == 0 => statement_start = index + 1,
+            keyword @ ("uniform" | "buffer") if depth == 0 => {
+                // The declaration ends at the first `;` outside of the braces of a block
+                let mut end = index;
+                let mut block_depth = 0usize;
+                while end < tokens.len() {
+                    match tokens[end].text.as_str() {
+                        "{" => block_depth += 1,
+                        "}" => block_depth = block_depth.saturating_sub(1),
+                        ";" if block_depth == 0 => break,
+                        _ => {}
+                    }
+                    end += 1;
+                }
+
+                let declaration = &tokens[statement_start..end];
+                let keyword_index = index - statement_start;
+                if let Some((name, explicit)) = parse_block_declaration(declaration, keyword_index)
+                {
+                    if explicit {
+                        explicit_bindings.insert(name.to_string());
+                    }
+                } else if keyword == "uniform" {
+                    uniforms.extend(parse_uniform_declaration(declaration, data)?);
+                }
+                index = end;
+                statement_start = end + 1;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+
+    Ok((uniforms, explicit_bindings))
+}
+
+/// Name of the block a declaration like `layout(std430, binding = 1) buffer Particles { ... }` opens,
+/// and whether its layout sets the binding. None when the declaration isn't a block
+fn parse_block_declaration(tokens: &[GlslToken], keyword: usize) -> Option<(&str, bool)> {
+    let name = tokens.get(keyword + 1)?;
+    tokens.get(keyword + 2).filter(|token| token.text == "{")?;
+
+    let mut layout = Vec::new();
+    let mut index = 0;
+    while index < keyword {
+        index = match tokens[index].text.as_str() {
+            "layout" => parse_layout(tokens, index + 1, &mut layout),
+            _ => index + 1,
+        };
+    }
+
+    let explicit = layout.iter().any(|(entry, _)| entry == "binding");
+    Some((name.text.as_str(), explicit))
+}
+
+/// Reads a single declaration like `layout(binding = 0) uniform highp float a, b[4]`
+fn parse_uniform_declaration(
+    tokens: &[GlslToken],
+    data: &ProcessData,
+) -> Result<Vec<ProcessedUniform>, ShaderError> {
+    let mut layout = Vec::new();
+    let mut memory = Vec::new();
+    let mut precision = None;
+
+    // Qualifiers come before the type, in any order
+    let mut index = 0;
+    while let Some(token) = tokens.get(index) {
+        match token.text.as_str() {
+            "layout" => index = parse_layout(tokens, index + 1, &mut layout),
+            text if PRECISION_QUALIFIERS.contains(&text) => {
+                precision = Some(text.to_string());
+                index += 1;
+            }
+            text if MEMORY_QUALIFIERS.contains(&text) => {
+                memory.push(text.to_string());
+                index += 1;
+            }
+            "uniform" | "invariant" | "precise" => index += 1,
+            _ => break,
+        }
+    }
+
+    let Some(ty) = tokens.get(index) else {
+        return Err(token_error(data, tokens.last(), "Expected a uniform type"));
+    };
+    index += 1;
+
+    // Uniform blocks don't declare single uniforms
+    if tokens.get(index).is_some_and(|token| token.text == "{") {
+        return Ok(Vec::new());
+    }
+
+    // `vec3[4] lights` puts the size on the type, it applies to every name
+    let type_size = parse_array_size(tokens, &mut index, data)?;
+
+    let mut uniforms = Vec::new();
+    loop {
+        let name = match tokens.get(index) {
+            Some(token)
+                if token
+                    .text
+                    .starts_with(|char: char| char.is_alphabetic() || char == '_') =>
+            {
+                token
+            }
+            token => {
+                return Err(token_error(
+                    data,
+                    token.or(tokens.last()),
+                    "Expected a uniform name",
+                ));
+            }
+        };
+        index += 1;
+
+        let array_size = parse_array_size(tokens, &mut index, data)?.or(type_size);
+
+        // Initializers are skipped up to the next name
+        let mut parentheses = 0usize;
+        while let Some(token) = tokens.get(index) {
+            match token.text.as_str() {
+                "(" => parentheses += 1,
+                ")" => parentheses = parentheses.saturating_sub(1),
+                "," if parentheses == 0 => break,
+                _ => {}
+            }
+            index += 1;
+        }
+
+        uniforms.push(ProcessedUniform {
+            name: name.text.clone(),
+            ty: ty.text.clone(),
+            array_size,
+            layout: layout.clone(),
+            memory: memory.clone(),
+            precision: precision.clone(),
+        });
+
+        if tokens.get(index).is_none() {
+            return Ok(uniforms);
+        }
+        index += 1;
+    }
+}
+
+/// Reads the entries of `layout(...)`, starting at the opening parenthesis.
+/// Returns the index after the closing one
+fn parse_layout(
+    tokens: &[GlslToken],
+    mut index: usize,
+    layout: &mut Vec<(String, Option<String>)>,
+) -> usize {
+    if tokens.get(index).is_none_or(|token| token.text != "(") {
+        return index;
+    }
+    index += 1;
+
+    let mut entry: Vec<&str> = Vec::new();
+    while let Some(token) = tokens.get(index) {
+        index += 1;
+        match token.text.as_str() {
+            "," | ")" => {
+                if let Some((name, value)) = entry.split_first() {
+                    let value = value.iter().skip(1).copied().collect::<String>();
+                    layout.push((name.to_string(), (!value.is_empty()).then_some(value)));
+                }
+                entry.clear();
+                if token.text == ")" {
+                    break;
+                }
+            }
+            text => entry.push(text),
+        }
+    }
+
+    index
+}
+
+/// Reads an optional `[size]`, the size can use macros like `#if` expressions do.
+/// Sizes the preprocessor can't know, like `const int` ones, are None and come from the linked program
+fn parse_array_size(
+    tokens: &[GlslToken],
+    index: &mut usize,
+    data: &ProcessData,
+) -> Result<Option<usize>, ShaderError> {
+    let Some(open) = tokens.get(*index).filter(|token| token.text == "[") else {
+        return Ok(None);
+    };
+
+    let mut expression = Vec::new();
+    *index += 1;
+    while let Some(token) = tokens.get(*index) {
+        *index += 1;
+        if token.text == "]" {
+            break;
+        }
+        expression.push(token.text.as_str());
+    }
+
+    // Unsized arrays get their size from the initializer
+    if expression.is_empty() {
+        return Ok(None);
+    }
+
+    let expression = expression.join(" ");
+    match evaluate_constant(&expression, &data.defines) {
+        Some(size) if size > 0 => Ok(Some(size as usize)),
+        Some(size) => Err(token_error(
+            data,
+            Some(open),
+            &format!("Invalid array size {}", size),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// A preprocessor error at the position of a token
+fn token_error(data: &ProcessData, token: Option<&GlslToken>, message: &str) -> ShaderError {
+    match token {
+        Some(token) => ShaderError::preprocess(
+            Some(&data.included_files[token.file]),
+            Some(token.line),
+            message.to_string(),
+        ),
+        None => ShaderError::preprocess(None, None, message.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::source::MemorySource;
+
+    fn process(source: &str) -> Result<ProcessData, ShaderError> {
+        let options = ShaderOptions::new()
+            .source(MemorySource::new().with_file("test.comp", source))
+            .define("COUNT", 3);
+        process_shader(&mut HTMLLogger::new("test"), "test.comp", &options)
+    }
+
+    /// Processes the first file, the others can be included
+    fn process_files(files: &[(&str, &str)]) -> Result<ProcessData, ShaderError> {
+        let source = MemorySource::new();
+        for (path, contents) in files {
+            source.set_file(path, contents);
+        }
+        let options = ShaderOptions::new().source(source);
+        process_shader(&mut HTMLLogger::new("test"), files[0].0, &options)
+    }
+
+    /// Lines of the output, without the `#line` directives
+    fn output_lines(data: &ProcessData) -> Vec<&str> {
+        data.processed_source
+            .lines()
+            .filter(|line| !line.starts_with("#line"))
+            .collect()
+    }
+
+    fn error_message(error: ShaderError) -> String {
+        let ShaderError::Preprocess(diagnostics) = error else {
+            panic!("expected a preprocess error, got {:?}", error);
+        };
+        diagnostics[0].to_string()
+    }
+
+    fn array_size(data: &ProcessData, name: &str) -> Option<usize> {
+        let uniform = data.uniforms.iter().find(|uniform| uniform.name == name);
+        uniform.expect("uniform not found").array_size
+    }
+
+    #[test]
+    fn array_sizes_expand_macros() {
+        let data = process(
+            "#version 450\n\
+             #define DOUBLE(x) ((x) * 2)\n\
+             uniform float literal[4];\n\
+             uniform float injected[COUNT];\n\
+             uniform float expression[DOUBLE(COUNT) + 1];\n\
+             uniform float scalar;\n",
+        )
+        .unwrap();
+        assert_eq!(array_size(&data, "literal"), Some(4));
+        assert_eq!(array_size(&data, "injected"), Some(3));
+        assert_eq!(array_size(&data, "expression"), Some(7));
+        assert_eq!(array_size(&data, "scalar"), None);
+    }
+
+    #[test]
+    fn array_sizes_the_compiler_knows_are_left_to_it() {
+        let data = process(
+            "#version 450\n\
+             const int N = 4;\n\
+             uniform float constant[N];\n\
+             uniform float removed[MISSING];\n",
+        )
+        .unwrap();
+        assert_eq!(array_size(&data, "constant"), None);
+        assert_eq!(array_size(&data, "removed"), None);
+    }
+
+    #[test]
+    fn array_sizes_must_be_positive() {
+        assert!(process("#version 450\nuniform float empty[0];\n").is_err());
+        assert!(process("#version 450\nuniform float negative[COUNT - 4];\n").is_err());
+    }
+
+    #[test]
+    fn blocks_with_a_layout_binding_are_explicit() {
+        let data = process(
+            "#version 450\n\
+             layout(std140, binding = 2) uniform Camera { mat4 view; };\n\
+             layout(std140) uniform Material { vec4 color; } material;\n\
+             layout(std430, binding = 0) readonly buffer Particles { vec4 positions[]; };\n\
+             buffer Counters { uint counts[]; };\n\
+             layout(binding = 1) uniform sampler2D albedo;\n",
+        )
+        .unwrap();
+
+        let mut explicit = data.explicit_bindings.iter().collect::<Vec<_>>();
+        explicit.sort();
+        assert_eq!(explicit, ["Camera", "Particles"]);
+        let uniforms = data.uniforms.iter().map(|uniform| uniform.name.as_str());
+        assert_eq!(uniforms.collect::<Vec<_>>(), ["albedo"]);
+    }
+
+    #[test]
+    fn pragma_once_files_are_included_once() {
+        let data = process_files(&[
+            (
+                "main.comp",
+                "#include \"common.glsl\"\n#include \"common.glsl\"\nvoid main() {}\n",
+            ),
+            ("common.glsl", "#pragma once\nfloat common_value;\n"),
+        ])
+        .unwrap();
+        assert_eq!(
+            output_lines(&data),
+            ["float common_value;", "void main() {}"]
+        );
+    }
+
+    #[test]
+    fn include_guards_are_detected() {
+        let guarded = "// Shared values\n#ifndef COMMON_GLSL\n#define COMMON_GLSL\nfloat common_value;\n#endif\n";
+        let data = process_files(&[
+            (
+                "main.comp",
+                "#include \"common.glsl\"\n#include \"common.glsl\"\n",
+            ),
+            ("common.glsl", guarded),
+        ])
+        .unwrap();
+        assert_eq!(
+            data.include_guards.get("common.glsl").map(String::as_str),
+            Some("COMMON_GLSL")
+        );
+        assert_eq!(
+            output_lines(&data),
+            [
+                "// Shared values",
+                "#define COMMON_GLSL",
+                "float common_value;"
+            ]
+        );
+    }
+
+    #[test]
+    fn code_outside_the_guard_is_included_again() {
+        let unguarded = "#ifndef COMMON_GLSL\n#define COMMON_GLSL\n#endif\nfloat common_value;\n";
+        let data = process_files(&[
+            (
+                "main.comp",
+                "#include \"common.glsl\"\n#include \"common.glsl\"\n",
+            ),
+            ("common.glsl", unguarded),
+        ])
+        .unwrap();
+        assert!(data.include_guards.is_empty());
+        let count = output_lines(&data)
+            .iter()
+            .filter(|line| **line == "float common_value;")
+            .count();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn diamond_includes_emit_the_shared_file_once() {
+        for shared in [
+            "#pragma once\nfloat shared_value;\n",
+            "#ifndef SHARED\n#define SHARED\nfloat shared_value;\n#endif\n",
+        ] {
+            let data = process_files(&[
+                (
+                    "main.comp",
+                    "#include \"left.glsl\"\n#include \"right.glsl\"\n",
+                ),
+                ("left.glsl", "#include \"shared.glsl\"\nfloat left_value;\n"),
+                (
+                    "right.glsl",
+                    "#include \"shared.glsl\"\nfloat right_value;\n",
+                ),
+                ("shared.glsl", shared),
+            ])
+            .unwrap();
+            let lines = output_lines(&data);
+            let values = lines
+                .iter()
+                .filter(|line| line.starts_with("float"))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                values,
+                [
+                    &"float shared_value;",
+                    &"float left_value;",
+                    &"float right_value;"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let error = process_files(&[
+            ("main.comp", "#include \"other.glsl\"\n"),
+            ("other.glsl", "#include \"main.comp\"\n"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error_message(error),
+            "other.glsl: error: Include cycle: main.comp → other.glsl → main.comp"
+        );
+    }
+
+    #[test]
+    fn protected_files_still_report_cycles() {
+        let error = process_files(&[
+            ("main.comp", "#pragma once\n#include \"other.glsl\"\n"),
+            ("other.glsl", "#pragma once\n#include \"main.comp\"\n"),
+        ])
+        .unwrap_err();
+        assert!(error_message(error).contains("Include cycle: main.comp → other.glsl → main.comp"));
+
+        let error = process_files(&[("main.comp", "#include \"main.comp\"\n")]).unwrap_err();
+        assert!(error_message(error).contains("Include cycle: main.comp → main.comp"));
+    }
+
+    #[test]
+    fn nested_conditionals_pick_one_branch() {
+        let data = process_files(&[(
+            "main.comp",
+            "#define MODE 2\n\
+             #if MODE == 1\n\
+             float one;\n\
+             #elif MODE == 2\n\
+             #ifdef MISSING\n\
+             float missing;\n\
+             #else\n\
+             float two;\n\
+             #endif\n\
+             #elif MODE == 2\n\
+             float taken_twice;\n\
+             #else\n\
+             float other;\n\
+             #endif\n\
+             #if 0\n\
+             #if 1\n\
+             float inside_inactive;\n\
+             #endif\n\
+             #endif\n",
+        )])
+        .unwrap();
+        assert_eq!(output_lines(&data), ["#define MODE 2", "float two;"]);
+    }
+
+    #[test]
+    fn self_referencing_macros_are_zero() {
+        let data = process_files(&[(
+            "main.comp",
+            "#define FOO FOO\n#if FOO\nfloat defined_foo;\n#else\nfloat zero_foo;\n#endif\n",
+        )])
+        .unwrap();
+        assert_eq!(output_lines(&data), ["#define FOO FOO", "float zero_foo;"]);
+    }
+
+    #[test]
+    fn inactive_branches_skip_their_includes() {
+        let data = process_files(&[(
+            "main.comp",
+            "#ifdef MISSING\n#include \"missing.glsl\"\n#endif\nvoid main() {}\n",
+        )])
+        .unwrap();
+        assert_eq!(output_lines(&data), ["void main() {}"]);
+
+        let error = process_files(&[("main.comp", "#include \"missing.glsl\"\n")]).unwrap_err();
+        assert!(matches!(error, ShaderError::Io(_)));
+    }
+
+    #[test]
+    fn removed_lines_keep_the_source_map_aligned() {
+        let data = process_files(&[
+            (
+                "main.comp",
+                "#if 0\nfloat removed;\n#endif\n#include \"common.glsl\"\nfloat after;\n",
+            ),
+            (
+                "common.glsl",
+                "#ifdef MISSING\nfloat removed;\n#endif\nfloat common_value;\n",
+            ),
+        ])
+        .unwrap();
+        assert_eq!(
+            data.processed_source.lines().collect::<Vec<_>>(),
+            ["float common_value;", "float after;"]
+        );
+        assert_eq!(
+            data.line_to_source,
+            [("common.glsl".to_string(), 4), ("main.comp".to_string(), 5)]
+        );
+    }
+
+    #[test]
+    fn conditional_errors_point_to_the_directive() {
+        let cases = [
+            (
+                "#version 450\n#if 1\nfloat a;\n",
+                "main.comp:2: error: Unterminated conditional directive",
+            ),
+            (
+                "#if 1\n#else\n#elif 1\n#endif\n",
+                "main.comp:3: error: #elif after #else",
+            ),
+            (
+                "float a;\n#endif\n",
+                "main.comp:2: error: #endif without #if",
+            ),
+            ("#else\n", "main.comp:1: error: #else without #if"),
+            (
+                "#if 1\n#else\n#else\n#endif\n",
+                "main.comp:3: error: #else after #else",
+            ),
+            (
+                "#if (1\n#endif\n",
+                "main.comp:1: error: Expected ')' at the end of the expression",
+            ),
+        ];
+        for (source, expected) in cases {
+            let error = process_files(&[("main.comp", source)]).unwrap_err();
+            assert_eq!(error_message(error), expected);
+        }
+    }
+
+    /// A root file including `inc.glsl` between its lines, with two injected defines
+    fn process_with_defines(root: &str, line_directives: bool) -> ProcessData {
+        let source = MemorySource::new()
+            .with_file("main.comp", root)
+            .with_file("inc.glsl", "float i;\nfloat j;\n");
+        let options = ShaderOptions::new()
+            .source(source)
+            .define("A", 1)
+            .define("B", 2)
+            .line_directives(line_directives);
+        process_shader(&mut HTMLLogger::new("test"), "main.comp", &options).unwrap()
+    }
+
+    /// Follows the `#line` directives like the driver does, giving the source string number and line of every
+    /// output line that isn't a directive, next to the position `line_to_source` has for it
+    fn driver_positions(data: &ProcessData) -> Vec<((usize, usize), (String, usize))> {
+        let mut position = (0, 1);
+        let mut positions = Vec::new();
+        for (index, line) in data.processed_source.lines().enumerate() {
+            if let Some(directive) = line.strip_prefix("#line ") {
+                let (line, source) = directive.split_once(' ').unwrap();
+                position = (source.parse().unwrap(), line.parse().unwrap());
+                continue;
+            }
+            positions.push((position, data.line_to_source[index].clone()));
+            position.1 += 1;
+        }
+        positions
+    }
+
+    #[test]
+    fn line_directives_follow_every_jump() {
+        let data = process_with_defines(
+            "#version 450\nfloat a;\n#include \"inc.glsl\"\nfloat b;\n",
+            true,
+        );
+        assert_eq!(
+            data.processed_source,
+            "#version 450\n\
+             #line 1 1\n\
+             #define A 1\n\
+             #define B 2\n\
+             #line 2 0\n\
+             float a;\n\
+             #line 1 2\n\
+             float i;\n\
+             float j;\n\
+             #line 4 0\n\
+             float b;\n"
+        );
+
+        // Source string numbers are the indexes of the files
+        assert_eq!(
+            data.included_files,
+            ["main.comp", DEFINES_SOURCE, "inc.glsl"]
+        );
+        assert_eq!(
+            data.line_to_source.len(),
+            data.processed_source.lines().count()
+        );
+        for ((source, line), expected) in driver_positions(&data) {
+            assert_eq!(data.resolve(source, line), Some(expected));
+        }
+    }
+
+    #[test]
+    fn defines_come_first_without_a_version() {
+        let data = process_with_defines("float a;\n#include \"inc.glsl\"\nfloat b;\n", true);
+        assert_eq!(
+            data.processed_source,
+            "#line 1 2\n\
+             #define A 1\n\
+             #define B 2\n\
+             #line 1 0\n\
+             float a;\n\
+             #line 1 1\n\
+             float i;\n\
+             float j;\n\
+             #line 3 0\n\
+             float b;\n"
+        );
+
+        // The defines are only added once every file is processed, so they come last
+        assert_eq!(
+            data.included_files,
+            ["main.comp", "inc.glsl", DEFINES_SOURCE]
+        );
+        assert_eq!(
+            data.line_to_source.len(),
+            data.processed_source.lines().count()
+        );
+        for ((source, line), expected) in driver_positions(&data) {
+            assert_eq!(data.resolve(source, line), Some(expected));
+        }
+    }
+
+    #[test]
+    fn defines_follow_the_version_without_directives() {
+        let data = process_with_defines("#version 450\nfloat a;\n", false);
+        assert_eq!(
+            output_lines(&data),
+            ["#version 450", "#define A 1", "#define B 2", "float a;"]
+        );
+        assert_eq!(
+            data.line_to_source,
+            [
+                ("main.comp".to_string(), 1),
+                (DEFINES_SOURCE.to_string(), 1),
+                (DEFINES_SOURCE.to_string(), 2),
+                ("main.comp".to_string(), 2),
+            ]
+        );
+        assert_eq!(data.sources[DEFINES_SOURCE], "#define A 1\n#define B 2\n");
+    }
+
+    #[test]
+    fn resolve_maps_output_lines_without_directives() {
+        let data = process_with_defines("float a;\n#include \"inc.glsl\"\nfloat b;\n", false);
+        assert_eq!(
+            output_lines(&data),
+            [
+                "#define A 1",
+                "#define B 2",
+                "float a;",
+                "float i;",
+                "float j;",
+                "float b;"
+            ]
+        );
+
+        // Without directives the driver counts the lines of the whole output, whatever the source number
+        let resolve = |line| data.resolve(0, line);
+        assert_eq!(resolve(2), Some((DEFINES_SOURCE.to_string(), 2)));
+        assert_eq!(resolve(3), Some(("main.comp".to_string(), 1)));
+        assert_eq!(resolve(5), Some(("inc.glsl".to_string(), 2)));
+        assert_eq!(resolve(6), Some(("main.comp".to_string(), 3)));
+        assert_eq!(resolve(7), None);
+        assert_eq!(resolve(0), None);
+    }
+
+    #[test]
+    fn resolve_uses_the_source_number_of_directives() {
+        let data = process_with_defines(
+            "#version 450\nfloat a;\n#include \"inc.glsl\"\nfloat b;\n",
+            true,
+        );
+        assert_eq!(data.resolve(0, 4), Some(("main.comp".to_string(), 4)));
+        assert_eq!(data.resolve(1, 2), Some((DEFINES_SOURCE.to_string(), 2)));
+        assert_eq!(data.resolve(2, 1), Some(("inc.glsl".to_string(), 1)));
+        assert_eq!(data.resolve(3, 1), None);
+        assert_eq!(data.resolve(2, 0), None);
+    }
+}
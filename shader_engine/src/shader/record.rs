@@ -0,0 +1,82 @@
+use crate::shader::preprocessor::DEFINES_SOURCE;
+use crate::shader::uniform::Uniform;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::{Rc, Weak};
+
+thread_local! {
+    /// Records of every live program, the programs own them so dropped programs disappear by themselves
+    static PROGRAMS: RefCell<Vec<Weak<RefCell<ProgramRecord>>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// What a screenshot needs to know to reproduce a program, kept up to date on reloads
+#[derive(Default)]
+pub(crate) struct ProgramRecord {
+    pub(crate) name: String,
+    /// Files the program was built from with the hash of their contents
+    pub(crate) files: Vec<(String, u64)>,
+    pub(crate) defines: Vec<(String, String)>,
+    pub(crate) uniforms: Vec<Rc<RefCell<dyn Uniform>>>,
+}
+
+/// Creates an empty record and adds it to the live programs
+pub(crate) fn register_program() -> Rc<RefCell<ProgramRecord>> {
+    let record = Rc::new(RefCell::new(ProgramRecord::default()));
+    PROGRAMS.with(|programs| programs.borrow_mut().push(Rc::downgrade(&record)));
+    record
+}
+
+/// Hashes the contents of the files with FNV-1a, so the same sources give the same hash on every run
+pub(crate) fn hash_source(contents: &str) -> u64 {
+    contents.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+/// Describes every live program, with the hashes of its files, its defines and the current values of its uniforms
+pub(crate) fn describe_programs() -> String {
+    let records = PROGRAMS.with(|programs| {
+        let mut programs = programs.borrow_mut();
+        programs.retain(|record| record.strong_count() > 0);
+        programs
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>()
+    });
+
+    let mut description = String::new();
+    for record in records {
+        let record = record.borrow();
+        let _ = writeln!(description, "[{}]", record.name);
+
+        for (file, hash) in record.files.iter() {
+            if file != DEFINES_SOURCE {
+                let _ = writeln!(description, "file {} = {:016x}", file, hash);
+            }
+        }
+        for (name, value) in record.defines.iter() {
+            let _ = writeln!(description, "define {} = {}", name, value);
+        }
+
+        let mut uniforms = record
+            .uniforms
+            .iter()
+            .map(|uniform| {
+                let uniform = uniform.borrow();
+                format!(
+                    "uniform {} = {}",
+                    uniform.to_string(),
+                    uniform.value_string()
+                )
+            })
+            .collect::<Vec<_>>();
+        uniforms.sort();
+        for uniform in uniforms {
+            let _ = writeln!(description, "{}", uniform);
+        }
+
+        description.push('\n');
+    }
+
+    description
+}
@@ -1,16 +1,22 @@
 use crate::quote;
+use crate::shader::diagnostic::{Diagnostic, ShaderError};
 use crate::shader::error_handler::check_shader;
-use crate::shader::preprocessor::{process_shader, ProcessData, SHADER_FILE_PREFIX};
+use crate::shader::options::ShaderOptions;
+use crate::shader::preprocessor::{process_shader, ProcessData, DEFINES_SOURCE};
+use crate::shader::source::ShaderSource;
 use crate::utils::colorized_text::Colorize;
 use crate::utils::file_watcher::FileWatcher;
 use crate::utils::html_logger::HTMLLogger;
 use gl::types::GLuint;
 use std::ffi::CString;
 use std::ptr;
+use std::rc::Rc;
 
 pub struct Shader {
     pub(crate) id: GLuint,
+    pub(crate) shader_type: GLuint,
     pub(crate) data: ProcessData,
+    pub(crate) diagnostics: Vec<Diagnostic>,
     pub(crate) watchers: Vec<FileWatcher>,
 }
 
@@ -19,14 +25,18 @@ impl Shader {
         logger: &mut HTMLLogger,
         file_name: &str,
         shader_type: GLuint,
-    ) -> Result<Shader, String> {
+        options: &ShaderOptions,
+    ) -> Result<Shader, ShaderError> {
         logger.open_scope("Compiling ".yellow() + quote!(file_name).magenta());
 
-        let data = match process_shader(logger, file_name) {
+        let data = match process_shader(logger, file_name, options) {
             Ok(data) => data,
             Err(e) => {
                 logger.close_scope();
-                logger.error(e.as_str().red());
+                for diagnostic in e.diagnostics() {
+                    logger.log(diagnostic.to_string().red());
+                }
+                logger.panic();
                 return Err(e);
             }
         };
@@ -41,12 +51,18 @@ impl Shader {
         }
 
         logger.close_scope();
-        check_shader(logger, id, &data)?;
+        let diagnostics = check_shader(logger, id, &data, options)?;
 
         // Create a watcher for the file
-        let watchers = get_file_watchers(&data.included_files);
+        let watchers = get_file_watchers(&data.included_files, &options.source);
 
-        Ok(Shader { id, data, watchers })
+        Ok(Shader {
+            id,
+            shader_type,
+            data,
+            diagnostics,
+            watchers,
+        })
     }
 }
 
@@ -58,10 +74,23 @@ impl Drop for Shader {
     }
 }
 
-fn get_file_watchers(files: &[String]) -> Vec<FileWatcher> {
+/// Name of a shader stage, as the drivers write it in their logs
+pub(crate) fn stage_name(shader_type: GLuint) -> &'static str {
+    match shader_type {
+        gl::VERTEX_SHADER => "vertex",
+        gl::FRAGMENT_SHADER => "fragment",
+        gl::GEOMETRY_SHADER => "geometry",
+        gl::TESS_CONTROL_SHADER => "tessellation control",
+        gl::TESS_EVALUATION_SHADER => "tessellation evaluation",
+        gl::COMPUTE_SHADER => "compute",
+        _ => "unknown",
+    }
+}
+
+fn get_file_watchers(files: &[String], source: &Rc<dyn ShaderSource>) -> Vec<FileWatcher> {
     let mut watchers = Vec::new();
-    for file in files.iter() {
-        watchers.push(FileWatcher::new(SHADER_FILE_PREFIX.to_owned() + file));
+    for file in files.iter().filter(|file| *file != DEFINES_SOURCE) {
+        watchers.push(FileWatcher::new(file.clone(), source.clone()));
     }
     watchers
 }
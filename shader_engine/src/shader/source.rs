@@ -0,0 +1,204 @@
+use crate::quote;
+use crate::shader::options::DEFAULT_SHADER_ROOT;
+use crate::shader::preprocessor::normalize_path;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Where the preprocessor reads shader files from
+pub trait ShaderSource: Debug {
+    /// Checks if the file exists in this source
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Reads the whole file as a string
+    fn read(&self, path: &Path) -> Result<String, String>;
+
+    /// Last modification time of the file, used by the watchers.
+    /// Sources that never change return None
+    fn modified(&self, path: &Path) -> Option<SystemTime>;
+
+    /// Roots used when the ShaderOptions don't have any
+    fn default_roots(&self) -> Vec<PathBuf> {
+        vec![PathBuf::new()]
+    }
+}
+
+/// Reads the shaders from the real file system
+#[derive(Debug, Default)]
+pub struct DiskSource;
+
+impl ShaderSource for DiskSource {
+    fn exists(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn read(&self, path: &Path) -> Result<String, String> {
+        fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", quote!(path.display()), e))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    fn default_roots(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(DEFAULT_SHADER_ROOT)]
+    }
+}
+
+/// Keeps the shaders as strings in memory.
+/// Files can be replaced at any time, programs built from this source reload on `check_watchers`
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    files: RefCell<HashMap<PathBuf, (String, SystemTime)>>,
+}
+
+impl MemorySource {
+    pub fn new() -> MemorySource {
+        MemorySource::default()
+    }
+
+    pub fn with_file<P: AsRef<Path>>(self, path: P, contents: &str) -> MemorySource {
+        self.set_file(path, contents);
+        self
+    }
+
+    pub fn set_file<P: AsRef<Path>>(&self, path: P, contents: &str) {
+        self.files.borrow_mut().insert(
+            normalize_path(path.as_ref()),
+            (contents.to_string(), SystemTime::now()),
+        );
+    }
+
+    pub fn remove_file<P: AsRef<Path>>(&self, path: P) {
+        self.files
+            .borrow_mut()
+            .remove(&normalize_path(path.as_ref()));
+    }
+}
+
+impl ShaderSource for MemorySource {
+    fn exists(&self, path: &Path) -> bool {
+        self.files.borrow().contains_key(&normalize_path(path))
+    }
+
+    fn read(&self, path: &Path) -> Result<String, String> {
+        self.files
+            .borrow()
+            .get(&normalize_path(path))
+            .map(|(contents, _)| contents.clone())
+            .ok_or_else(|| format!("{} is not in the memory source", quote!(path.display())))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        self.files
+            .borrow()
+            .get(&normalize_path(path))
+            .map(|(_, modified)| *modified)
+    }
+}
+
+/// Shaders compiled into the binary, usually created with `embed_shaders!`
+#[derive(Debug)]
+pub struct EmbeddedSource {
+    files: HashMap<PathBuf, &'static [u8]>,
+}
+
+impl EmbeddedSource {
+    pub fn new(files: &[(&str, &'static [u8])]) -> EmbeddedSource {
+        EmbeddedSource {
+            files: files
+                .iter()
+                .map(|(path, contents)| (normalize_path(Path::new(path)), *contents))
+                .collect(),
+        }
+    }
+}
+
+impl ShaderSource for EmbeddedSource {
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize_path(path))
+    }
+
+    fn read(&self, path: &Path) -> Result<String, String> {
+        let contents = self
+            .files
+            .get(&normalize_path(path))
+            .ok_or_else(|| format!("{} is not embedded", quote!(path.display())))?;
+        String::from_utf8(contents.to_vec())
+            .map_err(|_| format!("{} is not valid UTF-8", quote!(path.display())))
+    }
+
+    fn modified(&self, _path: &Path) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// Embeds shader files into the binary, the paths are relative to the given directory
+/// and the files are found at the same relative paths in the created `EmbeddedSource`
+///
+/// `embed_shaders!(concat!(env!("CARGO_MANIFEST_DIR"), "/res/shaders"), ["main.comp", "utils/math.glsl"])`
+#[macro_export]
+macro_rules! embed_shaders {
+    ($directory:expr, [$($file:literal),* $(,)?]) => {
+        $crate::shader::source::EmbeddedSource::new(&[
+            $(($file, include_bytes!(concat!($directory, "/", $file)).as_slice()),)*
+        ])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn memory_paths_are_normalized() {
+        let source = MemorySource::new().with_file("./a/../b.glsl", "b");
+        assert!(source.exists(Path::new("b.glsl")));
+        assert!(source.exists(Path::new("c/../b.glsl")));
+        assert_eq!(source.read(Path::new("./b.glsl")).unwrap(), "b");
+
+        source.remove_file("a/./../b.glsl");
+        assert!(!source.exists(Path::new("b.glsl")));
+    }
+
+    #[test]
+    fn missing_memory_files_are_errors() {
+        let source = MemorySource::new().with_file("main.comp", "");
+        assert!(!source.exists(Path::new("missing.glsl")));
+        let error = source.read(Path::new("missing.glsl")).unwrap_err();
+        assert!(error.contains("missing.glsl"), "{}", error);
+        assert_eq!(source.modified(Path::new("missing.glsl")), None);
+    }
+
+    #[test]
+    fn memory_updates_change_the_modification_time() {
+        let source = MemorySource::new().with_file("main.comp", "old");
+        let before = source.modified(Path::new("main.comp")).unwrap();
+
+        thread::sleep(Duration::from_millis(5));
+        source.set_file("main.comp", "new");
+        assert!(source.modified(Path::new("main.comp")).unwrap() > before);
+        assert_eq!(source.read(Path::new("main.comp")).unwrap(), "new");
+    }
+
+    #[test]
+    fn embedded_files_never_change() {
+        let source =
+            EmbeddedSource::new(&[("./shaders/../main.comp", b"main"), ("bad.glsl", &[0xFF])]);
+        assert!(source.exists(Path::new("main.comp")));
+        assert_eq!(source.read(Path::new("main.comp")).unwrap(), "main");
+        assert_eq!(source.modified(Path::new("main.comp")), None);
+
+        assert!(!source.exists(Path::new("missing.glsl")));
+        assert!(source.read(Path::new("missing.glsl")).is_err());
+        assert!(source.read(Path::new("bad.glsl")).is_err());
+    }
+}
@@ -1,25 +1,39 @@
 use crate::shader::bindable::Bindable;
-use gl::types::{GLdouble, GLfloat, GLint, GLuint};
+use crate::shader::preprocessor::ProcessedUniform;
+use gl::types::{GLboolean, GLdouble, GLfloat, GLint, GLsizei, GLuint};
 use std::any::Any;
+use std::cell::RefCell;
 use std::ffi::CString;
+use std::fmt::Debug;
+use std::rc::Rc;
 
 pub struct UniformVariable<T> {
     name: String,
     ty: String,
     location: Option<GLint>,
     bind: Bindable<T>,
+    /// Only used by matrices, the inner arrays are read as rows instead of columns
+    transpose: bool,
+    /// Suffixes of every location the value is uploaded to, a single empty one unless it's a struct
+    paths: Vec<String>,
+    locations: Vec<GLint>,
 }
 
 #[allow(dead_code)]
 pub trait Uniform: Any {
     fn load_uniform(&self);
+    fn bind_program(&mut self, program: GLuint) -> Result<(), ()>;
     fn is_dirty(&self) -> bool;
+    fn mark_dirty(&mut self);
     fn clear_dirty(&mut self);
 
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
 
     fn to_string(&self) -> String;
+
+    /// Current value, as Debug prints it
+    fn value_string(&self) -> String;
 }
 
 impl<T> UniformVariable<T> {
@@ -29,17 +43,38 @@ impl<T> UniformVariable<T> {
             ty: ty.to_string(),
             location: None,
             bind: Bindable::new(initial),
+            transpose: false,
+            paths: vec![String::new()],
+            locations: Vec::new(),
         }
     }
 
+    /// Uploads to `name + path` for every path instead of `name`, used for struct uniforms
+    pub(crate) fn with_paths(mut self, paths: Vec<String>) -> UniformVariable<T> {
+        self.paths = paths;
+        self
+    }
+
+    /// Finds the locations in the program, fails if none of them are used by it
     pub fn bind_program(&mut self, program: GLuint) -> Result<(), ()> {
-        let uniform_name = CString::new(self.name.as_str()).unwrap();
-        let location = unsafe { gl::GetUniformLocation(program, uniform_name.as_ptr()) };
-        if location == -1 {
-            return Err(());
+        self.locations = self
+            .paths
+            .iter()
+            .map(|path| {
+                let uniform_name = CString::new(format!("{}{}", self.name, path)).unwrap();
+                unsafe { gl::GetUniformLocation(program, uniform_name.as_ptr()) }
+            })
+            .collect();
+
+        self.location = self
+            .locations
+            .iter()
+            .copied()
+            .find(|location| *location != -1);
+        match self.location {
+            Some(_) => Ok(()),
+            None => Err(()),
         }
-        self.location = Some(location);
-        Ok(())
     }
 
     #[allow(dead_code)]
@@ -54,9 +89,16 @@ impl<T> UniformVariable<T> {
     pub fn get_value(&self) -> &T {
         self.bind.get()
     }
+
+    /// Matrices are column major by default, like in GLSL.
+    /// With transpose, each inner array of a square matrix is a row instead
+    pub fn set_transpose(&mut self, transpose: bool) {
+        self.transpose = transpose;
+        self.bind.mark_dirty();
+    }
 }
 
-impl<T: 'static> Uniform for UniformVariable<T>
+impl<T: Debug + 'static> Uniform for UniformVariable<T>
 where
     UniformVariable<T>: LoadableUniform,
 {
@@ -66,10 +108,18 @@ where
         }
     }
 
+    fn bind_program(&mut self, program: GLuint) -> Result<(), ()> {
+        UniformVariable::bind_program(self, program)
+    }
+
     fn is_dirty(&self) -> bool {
         self.bind.is_dirty()
     }
 
+    fn mark_dirty(&mut self) {
+        self.bind.mark_dirty();
+    }
+
     fn clear_dirty(&mut self) {
         self.bind.clear_dirty();
     }
@@ -85,12 +135,81 @@ where
     fn to_string(&self) -> String {
         format!("{}: {}", self.name, self.ty)
     }
+
+    fn value_string(&self) -> String {
+        format!("{:?}", self.bind.get())
+    }
 }
 
 pub trait LoadableUniform {
     fn load_uniform(&self);
 }
 
+/// A value that can be a field of a UniformStruct, either a plain GLSL value or another struct
+pub trait UniformField {
+    /// Suffixes of the locations the field is uploaded to, like `.material.albedo`
+    fn field_paths() -> Vec<String> {
+        vec![String::new()]
+    }
+
+    fn field_count() -> usize {
+        1
+    }
+
+    /// Uploads the field, one location for each of its paths
+    fn load_field(&self, locations: &[GLint]);
+}
+
+/// A Rust struct mapped onto a GLSL struct, usually implemented with `#[derive(UniformStruct)]`
+pub trait UniformStruct: UniformField {
+    /// Name of the struct in GLSL, uniforms declared with this type are created for it
+    const GLSL_NAME: &'static str;
+}
+
+impl<T: UniformStruct + 'static> LoadableUniform for UniformVariable<T> {
+    fn load_uniform(&self) {
+        self.get_value().load_field(&self.locations);
+    }
+}
+
+impl<T: UniformStruct + 'static> LoadableUniform for UniformVariable<Vec<T>> {
+    fn load_uniform(&self) {
+        let field_count = T::field_count();
+        // Elements past the end of the GLSL array have no locations, they are ignored like in the other arrays
+        let element_count = self.locations.len() / field_count;
+        for run in self.bind.dirty_runs() {
+            for index in run.start.min(element_count)..run.end.min(element_count) {
+                let locations = &self.locations[index * field_count..(index + 1) * field_count];
+                self.get_value()[index].load_field(locations);
+            }
+        }
+    }
+}
+
+/// Creates the uniform for a struct registered in the ShaderOptions, an array gets one struct per element
+pub(crate) fn create_struct_uniform<T: UniformStruct + Clone + Default + Debug + 'static>(
+    uniform: &ProcessedUniform,
+) -> Rc<RefCell<dyn Uniform>> {
+    match uniform.array_size {
+        Some(size) => {
+            let ty = format!("{}[{}]", uniform.ty, size);
+            let paths = (0..size)
+                .flat_map(|index| {
+                    T::field_paths()
+                        .into_iter()
+                        .map(move |path| format!("[{}]{}", index, path))
+                })
+                .collect();
+            let variable = UniformVariable::new(&uniform.name, &ty, vec![T::default(); size]);
+            Rc::new(RefCell::new(variable.with_paths(paths)))
+        }
+        None => {
+            let variable = UniformVariable::new(&uniform.name, &uniform.ty, T::default());
+            Rc::new(RefCell::new(variable.with_paths(T::field_paths())))
+        }
+    }
+}
+
 macro_rules! impl_uniform_loader {
     // For loading literals
     ($ty:ty, [1], $gl_func:ident, $gl_type:ty) => {
@@ -101,6 +220,14 @@ macro_rules! impl_uniform_loader {
                 }
             }
         }
+
+        impl UniformField for $ty {
+            fn load_field(&self, locations: &[GLint]) {
+                unsafe {
+                    gl::$gl_func(locations[0], *self as $gl_type);
+                }
+            }
+        }
     };
 
     // For loading vectors
@@ -113,6 +240,82 @@ macro_rules! impl_uniform_loader {
                 }
             }
         }
+
+        impl UniformField for [$ty; $len] {
+            fn load_field(&self, locations: &[GLint]) {
+                let converted = self.map(|v| v as $gl_type);
+                unsafe {
+                    gl::$gl_func(locations[0], 1, converted.as_ptr());
+                }
+            }
+        }
+    };
+
+    // For loading matrices, stored as columns like GLSL does: matCxR is [[T; R]; C]
+    ($ty:ty, [$columns:expr; $rows:expr], $gl_func:ident) => {
+        impl LoadableUniform for UniformVariable<[[$ty; $rows]; $columns]> {
+            fn load_uniform(&self) {
+                unsafe {
+                    gl::$gl_func(
+                        self.location.unwrap(),
+                        1,
+                        self.transpose as GLboolean,
+                        self.get_value().as_ptr() as *const $ty,
+                    );
+                }
+            }
+        }
+
+        impl UniformField for [[$ty; $rows]; $columns] {
+            fn load_field(&self, locations: &[GLint]) {
+                unsafe {
+                    gl::$gl_func(locations[0], 1, gl::FALSE, self.as_ptr() as *const $ty);
+                }
+            }
+        }
+    };
+}
+
+// Arrays upload only the dirty runs, element locations are consecutive so a run starts at `location + start`
+macro_rules! impl_array_uniform_loader {
+    ($ty:ty, $gl_func:ident, $gl_type:ty, |$value:ident| $convert:expr) => {
+        impl LoadableUniform for UniformVariable<Vec<$ty>> {
+            fn load_uniform(&self) {
+                let location = self.location.unwrap();
+                for run in self.bind.dirty_runs() {
+                    let converted = self.get_value()[run.clone()]
+                        .iter()
+                        .map(|$value| $convert)
+                        .collect::<Vec<_>>();
+                    unsafe {
+                        gl::$gl_func(
+                            location + run.start as GLint,
+                            run.len() as GLsizei,
+                            converted.as_ptr() as *const $gl_type,
+                        );
+                    }
+                }
+            }
+        }
+    };
+
+    // Matrices also take the transpose flag
+    ($ty:ty, $gl_func:ident) => {
+        impl LoadableUniform for UniformVariable<Vec<$ty>> {
+            fn load_uniform(&self) {
+                let location = self.location.unwrap();
+                for run in self.bind.dirty_runs() {
+                    unsafe {
+                        gl::$gl_func(
+                            location + run.start as GLint,
+                            run.len() as GLsizei,
+                            self.transpose as GLboolean,
+                            self.get_value()[run].as_ptr() as *const _,
+                        );
+                    }
+                }
+            }
+        }
     };
 }
 
@@ -139,3 +342,123 @@ impl_uniform_loader!(f32, [4], Uniform4fv, GLfloat);
 impl_uniform_loader!(f64, [2], Uniform2dv, GLdouble);
 impl_uniform_loader!(f64, [3], Uniform3dv, GLdouble);
 impl_uniform_loader!(f64, [4], Uniform4dv, GLdouble);
+
+// Matrix types
+impl_uniform_loader!(GLfloat, [2; 2], UniformMatrix2fv);
+impl_uniform_loader!(GLfloat, [3; 3], UniformMatrix3fv);
+impl_uniform_loader!(GLfloat, [4; 4], UniformMatrix4fv);
+impl_uniform_loader!(GLfloat, [2; 3], UniformMatrix2x3fv);
+impl_uniform_loader!(GLfloat, [2; 4], UniformMatrix2x4fv);
+impl_uniform_loader!(GLfloat, [3; 2], UniformMatrix3x2fv);
+impl_uniform_loader!(GLfloat, [3; 4], UniformMatrix3x4fv);
+impl_uniform_loader!(GLfloat, [4; 2], UniformMatrix4x2fv);
+impl_uniform_loader!(GLfloat, [4; 3], UniformMatrix4x3fv);
+impl_uniform_loader!(GLdouble, [2; 2], UniformMatrix2dv);
+impl_uniform_loader!(GLdouble, [3; 3], UniformMatrix3dv);
+impl_uniform_loader!(GLdouble, [4; 4], UniformMatrix4dv);
+impl_uniform_loader!(GLdouble, [2; 3], UniformMatrix2x3dv);
+impl_uniform_loader!(GLdouble, [2; 4], UniformMatrix2x4dv);
+impl_uniform_loader!(GLdouble, [3; 2], UniformMatrix3x2dv);
+impl_uniform_loader!(GLdouble, [3; 4], UniformMatrix3x4dv);
+impl_uniform_loader!(GLdouble, [4; 2], UniformMatrix4x2dv);
+impl_uniform_loader!(GLdouble, [4; 3], UniformMatrix4x3dv);
+
+// Array types
+impl_array_uniform_loader!(bool, Uniform1iv, GLint, |v| *v as GLint);
+impl_array_uniform_loader!(i32, Uniform1iv, GLint, |v| *v);
+impl_array_uniform_loader!(u32, Uniform1uiv, GLuint, |v| *v);
+impl_array_uniform_loader!(f32, Uniform1fv, GLfloat, |v| *v);
+impl_array_uniform_loader!(f64, Uniform1dv, GLdouble, |v| *v);
+impl_array_uniform_loader!([bool; 2], Uniform2iv, GLint, |v| v.map(|b| b as GLint));
+impl_array_uniform_loader!([bool; 3], Uniform3iv, GLint, |v| v.map(|b| b as GLint));
+impl_array_uniform_loader!([bool; 4], Uniform4iv, GLint, |v| v.map(|b| b as GLint));
+impl_array_uniform_loader!([i32; 2], Uniform2iv, GLint, |v| *v);
+impl_array_uniform_loader!([i32; 3], Uniform3iv, GLint, |v| *v);
+impl_array_uniform_loader!([i32; 4], Uniform4iv, GLint, |v| *v);
+impl_array_uniform_loader!([u32; 2], Uniform2uiv, GLuint, |v| *v);
+impl_array_uniform_loader!([u32; 3], Uniform3uiv, GLuint, |v| *v);
+impl_array_uniform_loader!([u32; 4], Uniform4uiv, GLuint, |v| *v);
+impl_array_uniform_loader!([f32; 2], Uniform2fv, GLfloat, |v| *v);
+impl_array_uniform_loader!([f32; 3], Uniform3fv, GLfloat, |v| *v);
+impl_array_uniform_loader!([f32; 4], Uniform4fv, GLfloat, |v| *v);
+impl_array_uniform_loader!([f64; 2], Uniform2dv, GLdouble, |v| *v);
+impl_array_uniform_loader!([f64; 3], Uniform3dv, GLdouble, |v| *v);
+impl_array_uniform_loader!([f64; 4], Uniform4dv, GLdouble, |v| *v);
+impl_array_uniform_loader!([[f32; 2]; 2], UniformMatrix2fv);
+impl_array_uniform_loader!([[f32; 3]; 3], UniformMatrix3fv);
+impl_array_uniform_loader!([[f32; 4]; 4], UniformMatrix4fv);
+impl_array_uniform_loader!([[f32; 3]; 2], UniformMatrix2x3fv);
+impl_array_uniform_loader!([[f32; 4]; 2], UniformMatrix2x4fv);
+impl_array_uniform_loader!([[f32; 2]; 3], UniformMatrix3x2fv);
+impl_array_uniform_loader!([[f32; 4]; 3], UniformMatrix3x4fv);
+impl_array_uniform_loader!([[f32; 2]; 4], UniformMatrix4x2fv);
+impl_array_uniform_loader!([[f32; 3]; 4], UniformMatrix4x3fv);
+impl_array_uniform_loader!([[f64; 2]; 2], UniformMatrix2dv);
+impl_array_uniform_loader!([[f64; 3]; 3], UniformMatrix3dv);
+impl_array_uniform_loader!([[f64; 4]; 4], UniformMatrix4dv);
+impl_array_uniform_loader!([[f64; 3]; 2], UniformMatrix2x3dv);
+impl_array_uniform_loader!([[f64; 4]; 2], UniformMatrix2x4dv);
+impl_array_uniform_loader!([[f64; 2]; 3], UniformMatrix3x2dv);
+impl_array_uniform_loader!([[f64; 4]; 3], UniformMatrix3x4dv);
+impl_array_uniform_loader!([[f64; 2]; 4], UniformMatrix4x2dv);
+impl_array_uniform_loader!([[f64; 3]; 4], UniformMatrix4x3dv);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    thread_local! {
+        /// Locations every `Pair` was loaded into, in order
+        static LOADED: RefCell<Vec<Vec<GLint>>> = const { RefCell::new(Vec::new()) };
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Pair;
+
+    impl UniformField for Pair {
+        fn field_paths() -> Vec<String> {
+            vec![".first".to_string(), ".second".to_string()]
+        }
+
+        fn field_count() -> usize {
+            2
+        }
+
+        fn load_field(&self, locations: &[GLint]) {
+            LOADED.with(|loaded| loaded.borrow_mut().push(locations.to_vec()));
+        }
+    }
+
+    impl UniformStruct for Pair {
+        const GLSL_NAME: &'static str = "Pair";
+    }
+
+    fn pair_array(size: usize) -> UniformVariable<Vec<Pair>> {
+        let mut variable = UniformVariable::new("pairs", "Pair[2]", vec![Pair; size]);
+        variable.locations = vec![10, 11, 12, 13];
+        variable
+    }
+
+    fn loaded() -> Vec<Vec<GLint>> {
+        LOADED.with(|loaded| loaded.take())
+    }
+
+    #[test]
+    fn struct_arrays_load_every_element() {
+        LoadableUniform::load_uniform(&pair_array(2));
+        assert_eq!(loaded(), [vec![10, 11], vec![12, 13]]);
+    }
+
+    #[test]
+    fn struct_arrays_ignore_elements_past_the_glsl_array() {
+        let mut variable = pair_array(2);
+        variable.get_bind().set(vec![Pair; 4]);
+        LoadableUniform::load_uniform(&variable);
+        assert_eq!(loaded(), [vec![10, 11], vec![12, 13]]);
+
+        variable.get_bind().clear_dirty();
+        variable.get_bind().set_element(3, Pair);
+        LoadableUniform::load_uniform(&variable);
+        assert!(loaded().is_empty());
+    }
+}
@@ -1,16 +1,21 @@
+use crate::shader::source::ShaderSource;
+use std::path::Path;
+use std::rc::Rc;
 use std::time::SystemTime;
 
 pub struct FileWatcher {
     pub(crate) path: String,
-    last_modified: SystemTime,
+    source: Rc<dyn ShaderSource>,
+    last_modified: Option<SystemTime>,
 }
 
 impl FileWatcher {
-    pub fn new(path: String) -> Self {
-        let last_modified = get_modified_time(&path);
+    pub fn new(path: String, source: Rc<dyn ShaderSource>) -> Self {
+        let last_modified = source.modified(Path::new(&path));
 
         Self {
             path,
+            source,
             last_modified,
         }
     }
@@ -18,7 +23,8 @@ impl FileWatcher {
 
 impl FileWatcher {
     pub fn update(&mut self) -> bool {
-        let current_modified = get_modified_time(&self.path);
+        // A missing file is None, so it only counts as updated once it comes back
+        let current_modified = self.source.modified(Path::new(&self.path));
         if current_modified > self.last_modified {
             self.last_modified = current_modified;
             true
@@ -27,8 +33,3 @@ impl FileWatcher {
         }
     }
 }
-
-fn get_modified_time(path: &str) -> SystemTime {
-    let metadata = std::fs::metadata(path).unwrap();
-    metadata.modified().unwrap()
-}
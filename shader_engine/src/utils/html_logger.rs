@@ -3,7 +3,6 @@ use regex::Regex;
 use std::cell::RefCell;
 use std::fmt::Display;
 use std::fs;
-use std::path::Path;
 use std::rc::Rc;
 
 const COLORIZED_DECODER_REGEX: &str = r"(\x1b\[(\d{1,2})m(.*?)\x1b\[0m)";
@@ -74,6 +73,7 @@ const HTML_START: &str = r###"
 
         p {
             margin-left: 2rem;
+            white-space: pre-wrap;
         }
     </style>
 </head>
@@ -128,8 +128,10 @@ impl HTMLElement for Details {
 fn format_text(text: &str) -> String {
     let decoder_regex = Regex::new(COLORIZED_DECODER_REGEX).unwrap();
 
+    // Shader code and file names like "<defines>" must not be read as HTML
+    let text = escape_html(text);
     decoder_regex
-        .replace_all(text, |cap: &regex::Captures| {
+        .replace_all(&text, |cap: &regex::Captures| {
             let color = cap.get(2).unwrap().as_str();
             let text = cap.get(3).unwrap().as_str();
             format!(
@@ -140,6 +142,13 @@ fn format_text(text: &str) -> String {
         .into_owned()
 }
 
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 pub struct HTMLLogger {
     name: String,
     elements: Vec<Rc<RefCell<dyn HTMLElement>>>,
@@ -231,11 +240,8 @@ impl HTMLLogger {
         }
         html_content = HTML_START.to_string() + &html_content + "</body></html>";
 
-        // Generate the logs directory if it doesn't exist
-        let logs_dir = Path::new("logs");
-        if !logs_dir.exists() {
-            fs::create_dir("logs").unwrap();
-        }
+        // Generate the logs directory if it doesn't exist, engines on other threads may be creating it too
+        fs::create_dir_all("logs").unwrap();
 
         // Write the HTML content to the file
         fs::write(format!("logs/{}.html", self.name), html_content).unwrap();
@@ -1,8 +1,10 @@
+use crate::screenshot::capture;
 use gl::types::GLsizei;
 use sdl2::video::{GLContext, GLProfile, Window, WindowBuildError, WindowBuilder};
 use sdl2::{Sdl, VideoSubsystem};
 use std::ops::{Deref, DerefMut};
 use std::os::raw;
+use std::path::{Path, PathBuf};
 // pub trait ScreenManagerBuilder {
 //     fn build_screen_manager(&mut self) -> Result<ScreenManager, String>;
 // }
@@ -58,7 +60,6 @@ pub struct ShaderWindow {
 }
 
 pub struct ShaderWindowBuilder {
-    pub(crate) sdl: Sdl,
     video_subsystem: VideoSubsystem,
     window_builder: WindowBuilder,
     width: i32,
@@ -67,16 +68,15 @@ pub struct ShaderWindowBuilder {
 
 impl ShaderWindow {
     pub(crate) fn get_builder(
+        sdl: &Sdl,
         title: &str,
         width: i32,
         height: i32,
     ) -> Result<ShaderWindowBuilder, String> {
-        let sdl = sdl2::init()?;
         let video_subsystem = sdl.video()?;
         let window_builder = video_subsystem.window(title, width as u32, height as u32);
 
         Ok(ShaderWindowBuilder {
-            sdl,
             video_subsystem,
             window_builder,
             width,
@@ -84,6 +84,12 @@ impl ShaderWindow {
         })
     }
 
+    /// Saves the back buffer as a timestamped PNG in the directory, with a sidecar file describing the programs.
+    /// Call it after the frame is drawn and before it is swapped
+    pub fn screenshot<P: AsRef<Path>>(&self, directory: P) -> Result<PathBuf, String> {
+        capture(0, gl::BACK, self.width, self.height, directory.as_ref())
+    }
+
     pub(crate) fn swap_window(&mut self) {
         self.window.gl_swap_window();
     }
@@ -0,0 +1,92 @@
+use sdl2::keyboard::Keycode;
+use shader_engine::image_buffer::Image2D;
+use shader_engine::shader::options::ShaderOptions;
+use shader_engine::shader::source::MemorySource;
+use shader_engine::shader::{ComputeShader, ShaderProgram};
+use shader_engine::utils::html_logger::HTMLLogger;
+use shader_engine::{MainLoopResult, ShaderEngine};
+
+const COUNTER_SHADER: &str = "\
+#version 450 core
+layout (local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+uniform float step_size;
+layout (r32f, binding = 0) uniform image2D counter;
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    float count = imageLoad(counter, pixel).r;
+    imageStore(counter, pixel, vec4(count + step_size, 0.0, 0.0, 1.0));
+}
+";
+
+/// Adds `step_size` to every pixel once per frame, for the given number of frames
+fn run_counter(width: i32, height: i32, frames: u64, step_size: f32) -> Vec<f32> {
+    let mut shader_engine = ShaderEngine::create_headless(width, height).unwrap();
+    let mut logger = HTMLLogger::new("Headless Test");
+
+    let options =
+        ShaderOptions::new().source(MemorySource::new().with_file("counter.comp", COUNTER_SHADER));
+    let mut program =
+        ShaderProgram::<ComputeShader>::new(&mut logger, "Counter", "counter.comp", options)
+            .unwrap();
+    program
+        .get_uniform::<f32>("step_size")
+        .unwrap()
+        .borrow_mut()
+        .get_bind()
+        .set(step_size);
+
+    let counter = Image2D::new(width, height, gl::READ_WRITE, gl::R32F, gl::R32F);
+    counter.clear();
+
+    shader_engine.set_frame_limit(Some(frames));
+    shader_engine.set_headless_loop(|_| {
+        program.toggle_use();
+        counter.bind_as_image(0);
+        program.dispatch_for_size(width as u32, height as u32, 1);
+        program.toggle_use();
+        MainLoopResult::Continue
+    });
+    assert_eq!(shader_engine.frame_count(), frames);
+
+    counter.read_pixels::<f32>().unwrap()
+}
+
+// The test harness runs these on separate threads at the same time, each with its own engine
+#[test]
+fn runs_a_compute_program_every_frame() {
+    let pixels = run_counter(16, 8, 3, 1.0);
+    assert_eq!(pixels.len(), 16 * 8);
+    assert!(pixels.iter().all(|count| *count == 3.0));
+}
+
+#[test]
+fn engines_on_other_threads_are_independent() {
+    let pixels = run_counter(8, 8, 5, 0.5);
+    assert!(pixels.iter().all(|count| *count == 2.5));
+
+    // A second engine on the same thread after the first one is dropped
+    let pixels = run_counter(4, 4, 1, 2.0);
+    assert!(pixels.iter().all(|count| *count == 2.0));
+}
+
+#[test]
+fn run_headless_frame_drives_single_frames() {
+    let mut shader_engine = ShaderEngine::create_headless(4, 4).unwrap();
+    let mut times = Vec::new();
+    for _ in 0..2 {
+        shader_engine.run_headless_frame(|elapsed_time| {
+            times.push(elapsed_time);
+            MainLoopResult::Continue
+        });
+    }
+
+    assert_eq!(shader_engine.frame_count(), 2);
+    assert_eq!(times[0], 0.0);
+    assert!(
+        shader_engine
+            .set_screenshot_key(Some(Keycode::F12))
+            .is_err()
+    );
+}
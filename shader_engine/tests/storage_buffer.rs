@@ -0,0 +1,63 @@
+use shader_engine::ShaderEngine;
+use shader_engine::buffer::StorageBuffer;
+use shader_engine::shader::options::ShaderOptions;
+use shader_engine::shader::source::MemorySource;
+use shader_engine::shader::{ComputeShader, ShaderProgram};
+use shader_engine::utils::html_logger::HTMLLogger;
+
+#[test]
+fn mapped_elements_are_read_and_written_in_place() {
+    let _shader_engine = ShaderEngine::create_headless(4, 4).unwrap();
+    let mut buffer = StorageBuffer::from_slice(&[[1.0f32, 2.0], [3.0, 4.0], [5.0, 6.0]]);
+
+    {
+        let mut mapping = buffer.map().unwrap();
+        assert_eq!(mapping.len(), 3);
+        assert_eq!(mapping.get(1), [3.0, 4.0]);
+        mapping.set(2, &[7.0, 8.0]);
+        assert_eq!(mapping.get(2), [7.0, 8.0]);
+    }
+
+    assert_eq!(buffer.read_back(), [[1.0, 2.0], [3.0, 4.0], [7.0, 8.0]]);
+}
+
+#[test]
+fn empty_buffers_map_without_elements() {
+    let _shader_engine = ShaderEngine::create_headless(4, 4).unwrap();
+    let mut buffer = StorageBuffer::<[f32; 4]>::new();
+    assert!(buffer.map().unwrap().is_empty());
+}
+
+const BLOCKS_SHADER: &str = "\
+#version 450 core
+layout (local_size_x = 1) in;
+
+layout (std430) buffer Defaulted { float defaulted[]; };
+layout (std430, binding = 0) buffer Explicit { float explicit[]; };
+
+void main() {
+    explicit[0] = defaulted[0];
+}
+";
+
+#[test]
+fn only_blocks_without_a_binding_are_moved() {
+    let _shader_engine = ShaderEngine::create_headless(4, 4).unwrap();
+    let options =
+        ShaderOptions::new().source(MemorySource::new().with_file("blocks.comp", BLOCKS_SHADER));
+    let program = ShaderProgram::<ComputeShader>::new(
+        &mut HTMLLogger::new("Block Bindings Test"),
+        "Blocks",
+        "blocks.comp",
+        options,
+    )
+    .unwrap();
+
+    let explicit = program.interface().storage_block("Explicit").unwrap();
+    let defaulted = program.interface().storage_block("Defaulted").unwrap();
+    assert_eq!((explicit.binding, explicit.moved_from), (0, None));
+    assert_eq!((defaulted.binding, defaulted.moved_from), (1, Some(0)));
+
+    // The explicit block comes second, so it's the one a first come first served rule would move
+    assert!(defaulted.index < explicit.index);
+}
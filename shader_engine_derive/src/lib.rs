@@ -0,0 +1,189 @@
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Field, Fields};
+
+/// Maps a struct onto a GLSL struct uniform with the same name.
+/// Every field is uploaded to `name.field`, fields can be plain values or other UniformStructs
+#[proc_macro_derive(UniformStruct)]
+pub fn derive_uniform_struct(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let fields = match named_fields(&input, "UniformStruct") {
+        Ok(fields) => fields,
+        Err(error) => return error,
+    };
+
+    let name = &input.ident;
+    let glsl_name = name.to_string();
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let field_names = fields
+        .iter()
+        .map(|field| field.ident.as_ref().unwrap())
+        .collect::<Vec<_>>();
+    let field_paths = field_names
+        .iter()
+        .map(|field| format!(".{}", field))
+        .collect::<Vec<_>>();
+    let field_types = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+
+    let expanded = quote! {
+        impl #impl_generics ::shader_engine::shader::UniformField for #name #type_generics #where_clause {
+            fn field_paths() -> Vec<String> {
+                let mut paths = Vec::new();
+                #(
+                    for path in <#field_types as ::shader_engine::shader::UniformField>::field_paths() {
+                        paths.push(format!("{}{}", #field_paths, path));
+                    }
+                )*
+                paths
+            }
+
+            fn field_count() -> usize {
+                0 #(+ <#field_types as ::shader_engine::shader::UniformField>::field_count())*
+            }
+
+            #[allow(unused_assignments)]
+            fn load_field(&self, locations: &[i32]) {
+                let mut locations = locations;
+                #(
+                    let (field_locations, rest) = locations
+                        .split_at(<#field_types as ::shader_engine::shader::UniformField>::field_count());
+                    ::shader_engine::shader::UniformField::load_field(&self.#field_names, field_locations);
+                    locations = rest;
+                )*
+            }
+        }
+
+        impl #impl_generics ::shader_engine::shader::UniformStruct for #name #type_generics #where_clause {
+            const GLSL_NAME: &'static str = #glsl_name;
+        }
+    };
+
+    expanded.into()
+}
+
+/// Lays a struct out like a GLSL struct in a uniform or a storage block, with the std140 or std430 rules.
+/// Every field must implement BlockLayout too
+#[proc_macro_derive(BlockLayout)]
+pub fn derive_block_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let fields = match named_fields(&input, "BlockLayout") {
+        Ok(fields) => fields,
+        Err(error) => return error,
+    };
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let field_names = fields
+        .iter()
+        .map(|field| field.ident.as_ref().unwrap())
+        .collect::<Vec<_>>();
+    let field_paths = field_names
+        .iter()
+        .map(|field| format!(".{}", field))
+        .collect::<Vec<_>>();
+    let field_types = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+    // Prefixed so they can't clash with the locals of the generated functions
+    let field_values = field_names
+        .iter()
+        .map(|field| format_ident!("field_{}", field))
+        .collect::<Vec<_>>();
+
+    let expanded = quote! {
+        impl #impl_generics ::shader_engine::buffer::BlockLayout for #name #type_generics #where_clause {
+            fn alignment(rules: ::shader_engine::buffer::LayoutRules) -> usize {
+                let alignment = 1 #(.max(<#field_types as ::shader_engine::buffer::BlockLayout>::alignment(rules)))*;
+                rules.aggregate_alignment(alignment)
+            }
+
+            fn size(rules: ::shader_engine::buffer::LayoutRules) -> usize {
+                let mut offset = 0;
+                #(
+                    offset = ::shader_engine::buffer::layout::align_to(
+                        offset,
+                        <#field_types as ::shader_engine::buffer::BlockLayout>::alignment(rules),
+                    );
+                    offset += <#field_types as ::shader_engine::buffer::BlockLayout>::size(rules);
+                )*
+                ::shader_engine::buffer::layout::align_to(
+                    offset,
+                    <Self as ::shader_engine::buffer::BlockLayout>::alignment(rules),
+                )
+            }
+
+            #[allow(unused_assignments)]
+            fn write(&self, rules: ::shader_engine::buffer::LayoutRules, bytes: &mut [u8]) {
+                let mut offset = 0;
+                #(
+                    offset = ::shader_engine::buffer::layout::align_to(
+                        offset,
+                        <#field_types as ::shader_engine::buffer::BlockLayout>::alignment(rules),
+                    );
+                    ::shader_engine::buffer::BlockLayout::write(&self.#field_names, rules, &mut bytes[offset..]);
+                    offset += <#field_types as ::shader_engine::buffer::BlockLayout>::size(rules);
+                )*
+            }
+
+            #[allow(unused_assignments)]
+            fn read(rules: ::shader_engine::buffer::LayoutRules, bytes: &[u8]) -> Self {
+                let mut offset = 0;
+                #(
+                    offset = ::shader_engine::buffer::layout::align_to(
+                        offset,
+                        <#field_types as ::shader_engine::buffer::BlockLayout>::alignment(rules),
+                    );
+                    let #field_values =
+                        <#field_types as ::shader_engine::buffer::BlockLayout>::read(rules, &bytes[offset..]);
+                    offset += <#field_types as ::shader_engine::buffer::BlockLayout>::size(rules);
+                )*
+                #name {
+                    #(#field_names: #field_values,)*
+                }
+            }
+
+            fn member_offsets(rules: ::shader_engine::buffer::LayoutRules) -> Vec<(String, usize)> {
+                let mut offsets = Vec::new();
+                let mut offset = 0;
+                #(
+                    offset = ::shader_engine::buffer::layout::align_to(
+                        offset,
+                        <#field_types as ::shader_engine::buffer::BlockLayout>::alignment(rules),
+                    );
+                    for (path, member_offset) in <#field_types as ::shader_engine::buffer::BlockLayout>::member_offsets(rules) {
+                        offsets.push((format!("{}{}", #field_paths, path), offset + member_offset));
+                    }
+                    offset += <#field_types as ::shader_engine::buffer::BlockLayout>::size(rules);
+                )*
+                offsets
+            }
+        }
+
+        impl #impl_generics ::shader_engine::buffer::layout::ArrayElement for #name #type_generics #where_clause {}
+    };
+
+    expanded.into()
+}
+
+/// Fields of a struct with named fields, or the compile error to return
+fn named_fields<'a>(input: &'a DeriveInput, derive: &str) -> Result<Vec<&'a Field>, TokenStream> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(fields.named.iter().collect()),
+            _ => Err(error(
+                input,
+                &format!("{} needs a struct with named fields", derive),
+            )),
+        },
+        _ => Err(error(
+            input,
+            &format!("{} can only be derived for structs", derive),
+        )),
+    }
+}
+
+fn error(input: &DeriveInput, message: &str) -> TokenStream {
+    syn::Error::new_spanned(&input.ident, message)
+        .to_compile_error()
+        .into()
+}